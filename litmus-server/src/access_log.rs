@@ -0,0 +1,486 @@
+use std::fmt::Write;
+use std::str::FromStr;
+use std::time::Instant;
+
+use chrono::{DateTime, Local};
+
+/// The log target access log records are emitted under, this lets the
+/// logger send them to a separate sink from the server log.
+pub const ACCESS_LOG_TARGET: &str = "litmus::access";
+
+/// The Common Log Format.
+const COMMON_FORMAT: &str = r#"%h %l %u %t "%r" %>s %b"#;
+
+/// The Combined Log Format, the common format with the referer and user
+/// agent of the request.
+const COMBINED_FORMAT: &str =
+    r#"%h %l %u %t "%r" %>s %b "%{Referer}i" "%{User-Agent}i""#;
+
+/// A single part of an access log format string.
+#[derive(Debug, Clone)]
+enum Segment {
+    /// Text copied into the log line as is.
+    Literal(String),
+
+    /// `%h` the address of the client.
+    ClientHost,
+
+    /// `%l` and `%u` the remote logname and user, these are always `-`.
+    Unknown,
+
+    /// `%t` the time the request was received.
+    Time,
+
+    /// `%r` the first line of the request.
+    RequestLine,
+
+    /// `%m` the request method.
+    Method,
+
+    /// `%U` the path of the request without the query string.
+    Path,
+
+    /// `%q` the query string prefixed with a `?` if there is one.
+    Query,
+
+    /// `%H` the protocol of the request.
+    Protocol,
+
+    /// `%s` and `%>s` the status of the response.
+    Status,
+
+    /// `%B` the size of the response body in bytes.
+    Bytes,
+
+    /// `%b` the size of the response body in bytes, `-` when empty.
+    BytesClf,
+
+    /// `%D`, `%T` and `%{UNIT}T` the time taken to respond.
+    Duration(TimeUnit),
+
+    /// `%{NAME}i` the value of the request header with the given index
+    /// into the format's headers.
+    RequestHeader(usize),
+
+    /// `%L` the ID of the request.
+    RequestId,
+}
+
+/// The unit a duration is logged in.
+#[derive(Debug, Copy, Clone)]
+enum TimeUnit {
+    Seconds,
+    Milliseconds,
+    Microseconds,
+}
+
+/// A parsed access log format string.
+///
+/// Formats use the Apache `mod_log_config` directives, `common` and
+/// `combined` can be given to use the formats of the same names.
+#[derive(Debug, Clone)]
+pub struct AccessLogFormat {
+    segments: Vec<Segment>,
+
+    /// The lowercased names of the request headers used by the format.
+    headers: Vec<String>,
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = match s {
+            "common" => COMMON_FORMAT,
+            "combined" => COMBINED_FORMAT,
+            other => other,
+        };
+
+        let mut segments = Vec::new();
+        let mut headers = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                literal.push(c);
+                continue;
+            }
+
+            let mut argument = None;
+            match chars.peek() {
+                Some('{') => {
+                    chars.next();
+                    let mut arg = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => arg.push(c),
+                            None => {
+                                return Err(format!(
+                                    "unclosed argument in access log format {:?}",
+                                    s
+                                ))
+                            },
+                        }
+                    }
+                    argument = Some(arg);
+                },
+                // The final status, which is the only status there is.
+                Some('>') => {
+                    chars.next();
+                },
+                _ => {},
+            }
+
+            let directive = match chars.next() {
+                Some(d) => d,
+                None => {
+                    return Err(format!(
+                        "incomplete directive in access log format {:?}",
+                        s
+                    ))
+                },
+            };
+
+            let segment = match (directive, argument) {
+                ('%', None) => {
+                    literal.push('%');
+                    continue;
+                },
+                ('h', None) | ('a', None) => Segment::ClientHost,
+                ('l', None) | ('u', None) => Segment::Unknown,
+                ('t', None) => Segment::Time,
+                ('r', None) => Segment::RequestLine,
+                ('m', None) => Segment::Method,
+                ('U', None) => Segment::Path,
+                ('q', None) => Segment::Query,
+                ('H', None) => Segment::Protocol,
+                ('s', None) => Segment::Status,
+                ('B', None) => Segment::Bytes,
+                ('b', None) => Segment::BytesClf,
+                ('D', None) => Segment::Duration(TimeUnit::Microseconds),
+                ('T', None) => Segment::Duration(TimeUnit::Seconds),
+                ('T', Some(unit)) => match unit.as_str() {
+                    "s" => Segment::Duration(TimeUnit::Seconds),
+                    "ms" => Segment::Duration(TimeUnit::Milliseconds),
+                    "us" => Segment::Duration(TimeUnit::Microseconds),
+                    _ => return Err(format!("unknown time unit {:?}", unit)),
+                },
+                ('L', None) => Segment::RequestId,
+                ('i', Some(name)) => {
+                    headers.push(name.to_ascii_lowercase());
+                    Segment::RequestHeader(headers.len() - 1)
+                },
+                (d, _) => return Err(format!("unknown access log directive %{}", d)),
+            };
+
+            if !literal.is_empty() {
+                segments.push(Segment::Literal(std::mem::take(&mut literal)));
+            }
+            segments.push(segment);
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Self { segments, headers })
+    }
+}
+
+impl AccessLogFormat {
+    /// The lowercased names of the request headers the format logs, the
+    /// values are given to the record in the same order.
+    pub(crate) fn headers(&self) -> &[String] {
+        &self.headers
+    }
+
+    /// Formats the record into a log line.
+    pub(crate) fn format(&self, record: &AccessRecord) -> String {
+        let mut out = String::with_capacity(128);
+
+        for segment in self.segments.iter() {
+            let _ = match segment {
+                Segment::Literal(text) => write!(out, "{}", text),
+                Segment::ClientHost => write!(out, "{}", record.client),
+                Segment::Unknown => write!(out, "-"),
+                Segment::Time => {
+                    write!(out, "[{}]", record.time.format("%d/%b/%Y:%H:%M:%S %z"))
+                },
+                Segment::RequestLine => write!(
+                    out,
+                    "{} {} HTTP/{}",
+                    record.method,
+                    escape(&record.target),
+                    record.version
+                ),
+                Segment::Method => write!(out, "{}", record.method),
+                Segment::Path => {
+                    let path = record.target.split('?').next().unwrap_or("");
+                    write!(out, "{}", escape(path))
+                },
+                Segment::Query => match record.target.find('?') {
+                    Some(index) => write!(out, "{}", escape(&record.target[index..])),
+                    None => Ok(()),
+                },
+                Segment::Protocol => write!(out, "HTTP/{}", record.version),
+                Segment::Status => match record.status {
+                    Some(status) => write!(out, "{}", status),
+                    None => write!(out, "-"),
+                },
+                Segment::Bytes => write!(out, "{}", record.bytes_sent),
+                Segment::BytesClf if record.bytes_sent == 0 => write!(out, "-"),
+                Segment::BytesClf => write!(out, "{}", record.bytes_sent),
+                Segment::Duration(unit) => {
+                    let elapsed = record.started.elapsed();
+                    match unit {
+                        TimeUnit::Seconds => write!(out, "{}", elapsed.as_secs()),
+                        TimeUnit::Milliseconds => write!(out, "{}", elapsed.as_millis()),
+                        TimeUnit::Microseconds => write!(out, "{}", elapsed.as_micros()),
+                    }
+                },
+                Segment::RequestId => write!(out, "{}", escape(&record.request_id)),
+                Segment::RequestHeader(index) => {
+                    match record.headers.get(*index).and_then(|v| v.as_ref()) {
+                        Some(value) => write!(out, "{}", escape(value)),
+                        None => write!(out, "-"),
+                    }
+                },
+            };
+        }
+
+        out
+    }
+}
+
+/// Escapes quotes, backslashes and control characters so values taken
+/// from the request cannot break up or forge log lines.
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\x{:02x}", c as u32);
+            },
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+/// The details of a single request and its response logged once the
+/// response is complete.
+pub(crate) struct AccessRecord {
+    started: Instant,
+    time: DateTime<Local>,
+    client: String,
+    method: String,
+    target: String,
+    version: &'static str,
+    request_id: String,
+
+    /// The values of the headers used by the format.
+    headers: Vec<Option<String>>,
+
+    /// The status of the response once the response has started.
+    status: Option<u16>,
+    bytes_sent: usize,
+}
+
+impl AccessRecord {
+    pub(crate) fn new(
+        method: &str,
+        target: &str,
+        version: &'static str,
+        header_count: usize,
+    ) -> Self {
+        Self {
+            started: Instant::now(),
+            time: Local::now(),
+            client: "-".to_string(),
+            method: method.to_string(),
+            target: target.to_string(),
+            version,
+            request_id: "-".to_string(),
+            headers: vec![None; header_count],
+            status: None,
+            bytes_sent: 0,
+        }
+    }
+
+    /// Sets the address of the client.
+    pub(crate) fn set_client(&mut self, client: &str) {
+        self.client = client.to_string();
+    }
+
+    /// Sets the ID of the request.
+    pub(crate) fn set_request_id(&mut self, request_id: &str) {
+        self.request_id = request_id.to_string();
+    }
+
+    /// Sets the value of the header at the given index of the format's
+    /// headers, repeated headers are joined by a comma.
+    pub(crate) fn set_header(&mut self, index: usize, value: &[u8]) {
+        let value = String::from_utf8_lossy(value);
+        match &mut self.headers[index] {
+            Some(existing) => {
+                existing.push(',');
+                existing.push_str(&value);
+            },
+            slot => *slot = Some(value.into_owned()),
+        }
+    }
+
+    /// Records a chunk of the response as it is written, the first chunk
+    /// written starts with the status line and headers.
+    ///
+    /// Responses the server answers itself are written in a single chunk
+    /// so anything after the end of the headers is counted as body.
+    pub(crate) fn on_write(&mut self, data: &[u8]) {
+        if self.status.is_some() {
+            self.bytes_sent += data.len();
+            return;
+        }
+
+        self.status = response_status(data);
+        if let Some(end) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            self.bytes_sent += data.len() - end - 4;
+        }
+    }
+}
+
+/// Reads the status code from the start of a response.
+pub(crate) fn response_status(data: &[u8]) -> Option<u16> {
+    // The status line always starts with `HTTP/1.1 XXX`.
+    data.get(9..12)
+        .and_then(|s| std::str::from_utf8(s).ok())
+        .and_then(|s| s.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(target: &str) -> AccessRecord {
+        let mut record = AccessRecord::new("GET", target, "1.1", 0);
+        record.set_client("203.0.113.7");
+        record
+    }
+
+    fn format(format: &str, record: &AccessRecord) -> String {
+        format.parse::<AccessLogFormat>().unwrap().format(record)
+    }
+
+    #[test]
+    fn common_format() {
+        let mut record = record("/where?q=now");
+        record.on_write(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\n");
+        record.on_write(b"hello");
+
+        let line = format("common", &record);
+        let expected_time = record.time.format("[%d/%b/%Y:%H:%M:%S %z]").to_string();
+        assert_eq!(
+            line,
+            format!(
+                r#"203.0.113.7 - - {} "GET /where?q=now HTTP/1.1" 200 5"#,
+                expected_time
+            ),
+        );
+    }
+
+    #[test]
+    fn body_written_with_the_headers_is_counted() {
+        let mut record = record("/healthz");
+        record.on_write(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok");
+
+        assert_eq!(format("%s %B", &record), "200 2");
+    }
+
+    #[test]
+    fn combined_format_logs_the_referer_and_user_agent() {
+        let format: AccessLogFormat = "combined".parse().unwrap();
+        assert_eq!(format.headers(), ["referer", "user-agent"]);
+
+        let mut record = AccessRecord::new("GET", "/", "1.1", 2);
+        record.set_header(1, b"curl/8.0");
+        let line = format.format(&record);
+        assert!(
+            line.ends_with(r#""GET / HTTP/1.1" - - "-" "curl/8.0""#),
+            "{}",
+            line
+        );
+    }
+
+    #[test]
+    fn directives() {
+        let mut record = record("/a%20b?x=1");
+        record.set_request_id("abc-123");
+        record.on_write(b"HTTP/1.1 404 Not Found\r\n\r\n");
+
+        assert_eq!(
+            format("%a %m %U %q %H %s %>s %B %b %L 100%%", &record),
+            "203.0.113.7 GET /a%20b ?x=1 HTTP/1.1 404 404 0 - abc-123 100%",
+        );
+        assert_eq!(format("[%q]", &self::record("/")), "[]");
+    }
+
+    #[test]
+    fn durations() {
+        let record = record("/");
+        for directive in ["%D", "%T", "%{s}T", "%{ms}T", "%{us}T"] {
+            let value = format(directive, &record);
+            assert!(
+                value.parse::<u128>().is_ok(),
+                "{} gave {:?}",
+                directive,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn repeated_headers_are_joined() {
+        let format: AccessLogFormat = "%{X-Forwarded-For}i|%{accept}i".parse().unwrap();
+        assert_eq!(format.headers(), ["x-forwarded-for", "accept"]);
+
+        let mut record = AccessRecord::new("GET", "/", "1.1", 2);
+        record.set_header(0, b"10.0.0.1");
+        record.set_header(0, b"10.0.0.2");
+        assert_eq!(format.format(&record), "10.0.0.1,10.0.0.2|-");
+    }
+
+    #[test]
+    fn request_values_are_escaped() {
+        let format: AccessLogFormat = r#""%r" "%{user-agent}i""#.parse().unwrap();
+        let mut record = AccessRecord::new("GET", "/\"quoted\"\\", "1.1", 1);
+        record.set_header(0, b"evil\n127.0.0.1 - - forged");
+
+        assert_eq!(
+            format.format(&record),
+            r#""GET /\"quoted\"\\ HTTP/1.1" "evil\x0a127.0.0.1 - - forged""#,
+        );
+    }
+
+    #[test]
+    fn invalid_formats_are_rejected() {
+        assert!("%z".parse::<AccessLogFormat>().is_err());
+        assert!("%{referer".parse::<AccessLogFormat>().is_err());
+        assert!("trailing %".parse::<AccessLogFormat>().is_err());
+        assert!("%{ns}T".parse::<AccessLogFormat>().is_err());
+        assert!("%{name}m".parse::<AccessLogFormat>().is_err());
+    }
+
+    #[test]
+    fn response_status_is_read_from_the_status_line() {
+        assert_eq!(
+            response_status(b"HTTP/1.1 503 Service Unavailable\r\n"),
+            Some(503)
+        );
+        assert_eq!(response_status(b"HTTP/1.1 2"), None);
+        assert_eq!(response_status(b"garbage data"), None);
+    }
+}
@@ -0,0 +1,450 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crossbeam::queue::SegQueue;
+use pyo3::class::iter::IterNextOutput;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use pyo3::{PyAsyncProtocol, PyIterProtocol};
+
+use crate::responders::{
+    advance,
+    create_waiter,
+    Awaitable,
+    DataReceiver,
+    DataSender,
+    Poll,
+    ReceiveAwaitable,
+    SendAwaitable,
+};
+
+/// The ASGI version and spec version given to http scopes.
+const HTTP_SPEC_VERSION: &str = "2.3";
+
+/// The ASGI version and spec version given to lifespan scopes.
+const LIFESPAN_SPEC_VERSION: &str = "2.0";
+
+/// The ASGI version the adapter implements.
+const ASGI_VERSION: &str = "3.0";
+
+type Headers = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Adapts the LSGI (Litmus Server Gateway Interface) callback to an
+/// ASGI 3 application.
+///
+/// The adapter is called with `(scope, send, receive)` like any other LSGI
+/// callback and calls the app with `(scope, receive, send)`, where
+/// `receive` and `send` are awaitable callables that produce and accept
+/// ASGI event dicts.
+///
+/// Lifespan scopes are handed straight to the app as the lifespan
+/// handles already speak in ASGI events.
+///
+/// Args:
+///     app:
+///         The ASGI 3 application, this is any callable taking
+///         `(scope, receive, send)` and returning an awaitable.
+#[pyclass]
+pub struct ASGIAdapter {
+    /// The ASGI application.
+    app: PyObject,
+}
+
+#[pymethods]
+impl ASGIAdapter {
+    #[new]
+    fn new(app: PyObject) -> Self {
+        Self { app }
+    }
+
+    /// Invokes the app with the given LSGI scope and responders.
+    ///
+    /// Returns:
+    ///     The awaitable returned by the app.
+    #[call]
+    fn __call__(
+        &self,
+        py: Python,
+        scope: &PyDict,
+        send: PyObject,
+        receive: PyObject,
+    ) -> PyResult<PyObject> {
+        let scope_type: Option<&str> = match scope.get_item("type") {
+            Some(t) => Some(t.extract()?),
+            None => None,
+        };
+
+        let asgi = PyDict::new(py);
+        asgi.set_item("version", ASGI_VERSION)?;
+
+        if scope_type == Some("lifespan") {
+            asgi.set_item("spec_version", LIFESPAN_SPEC_VERSION)?;
+            scope.set_item("asgi", asgi)?;
+            return self.app.call1(py, (scope, receive, send));
+        }
+
+        asgi.set_item("spec_version", HTTP_SPEC_VERSION)?;
+        scope.set_item("asgi", asgi)?;
+
+        // Always present so apps can check for an extension without first
+        // checking for the key, the server supports none of them yet.
+        scope.set_item("extensions", PyDict::new(py))?;
+
+        let exchange = Arc::new(Exchange::default());
+        let receive = ASGIReceive {
+            receiver: receive.extract(py)?,
+            exchange: exchange.clone(),
+        };
+        let send = ASGISend {
+            sender: send.extract(py)?,
+            exchange,
+            response_started: false,
+            response_complete: false,
+        };
+
+        self.app
+            .call1(py, (scope, Py::new(py, receive)?, Py::new(py, send)?))
+    }
+}
+
+/// The state shared between the `receive` and `send` callables of a
+/// single request.
+#[derive(Default)]
+struct Exchange {
+    /// If the final chunk of the request body has been received.
+    request_complete: AtomicBool,
+
+    /// If the final chunk of the response body has been handed to the
+    /// server.
+    response_complete: AtomicBool,
+
+    /// Futures waiting for the `http.disconnect` event.
+    disconnect_waiters: SegQueue<PyObject>,
+}
+
+impl Exchange {
+    /// Marks the response as complete and wakes anything waiting for the
+    /// exchange to end.
+    fn complete_response(&self, py: Python) {
+        self.response_complete.store(true, Ordering::Relaxed);
+
+        while let Some(waker) = self.disconnect_waiters.pop() {
+            let _ = waker.call0(py);
+        }
+    }
+}
+
+/// The ASGI `receive` callable.
+#[pyclass]
+pub struct ASGIReceive {
+    receiver: Py<DataReceiver>,
+    exchange: Arc<Exchange>,
+}
+
+#[pymethods]
+impl ASGIReceive {
+    /// Waits for the next event from the client.
+    ///
+    /// Returns:
+    ///     An awaitable resolving to a `http.request` event while there
+    ///     is body left to read, followed by `http.disconnect` once the
+    ///     response has been sent or the client disconnects.
+    #[call]
+    fn __call__(&self, py: Python) -> ASGIReceiveAwaitable {
+        // Once the body is read the only event left is the disconnect.
+        let inner = if self.exchange.request_complete.load(Ordering::Relaxed) {
+            None
+        } else {
+            Some(ReceiveAwaitable::new(self.receiver.clone_ref(py), None))
+        };
+
+        ASGIReceiveAwaitable {
+            receiver: self.receiver.clone_ref(py),
+            inner,
+            exchange: self.exchange.clone(),
+            waiter: None,
+        }
+    }
+}
+
+/// The ASGI `send` callable.
+#[pyclass]
+pub struct ASGISend {
+    sender: Py<DataSender>,
+    exchange: Arc<Exchange>,
+
+    /// If `http.response.start` has been sent.
+    response_started: bool,
+
+    /// If the final `http.response.body` has been sent.
+    response_complete: bool,
+}
+
+#[pymethods]
+impl ASGISend {
+    /// Sends the given event to the client.
+    ///
+    /// Accepts the `http.response.start` and `http.response.body` events.
+    ///
+    /// Returns:
+    /// ```text
+    ///     An awaitable which completes once the event has been handed to
+    ///     the server.
+    /// ```
+    ///
+    /// Raises:
+    /// ```text
+    ///     RuntimeError:
+    ///         If the event is not expected at this point of the response
+    ///         or is of an unknown type.
+    ///
+    ///     ValueError:
+    ///         If the event contains an invalid status or header.
+    /// ```
+    #[call]
+    fn __call__(&mut self, py: Python, message: &PyDict) -> PyResult<ASGISendAwaitable> {
+        let message_type: &str = match message.get_item("type") {
+            Some(t) => t.extract()?,
+            None => return Err(PyValueError::new_err("message is missing a type")),
+        };
+
+        let unexpected = |state: &str| {
+            Err(PyRuntimeError::new_err(format!(
+                "unexpected ASGI message {:?} sent, {}",
+                message_type, state,
+            )))
+        };
+
+        let inner = match message_type {
+            "http.response.start" => {
+                if self.response_started {
+                    return unexpected("after the response was already started");
+                }
+
+                let status: u16 = match message.get_item("status") {
+                    Some(s) => s.extract()?,
+                    None => {
+                        return Err(PyValueError::new_err("missing response status"))
+                    },
+                };
+                let headers = match message.get_item("headers") {
+                    Some(h) => extract_headers(h)?,
+                    None => Vec::new(),
+                };
+
+                let headers = headers
+                    .iter()
+                    .map(|(name, value)| (name.as_slice(), value.as_slice()))
+                    .collect();
+                let inner = DataSender::send_start(
+                    self.sender.as_ref(py).borrow_mut(),
+                    status,
+                    headers,
+                )?;
+
+                self.response_started = true;
+                inner
+            },
+            "http.response.body" => {
+                if !self.response_started {
+                    return unexpected("before the response was started");
+                }
+
+                if self.response_complete {
+                    return unexpected("after the response was already completed");
+                }
+
+                let more_body: bool = match message.get_item("more_body") {
+                    Some(m) => m.extract()?,
+                    None => false,
+                };
+                let body: Vec<u8> = match message.get_item("body") {
+                    Some(b) => b.extract()?,
+                    None => Vec::new(),
+                };
+
+                let inner = DataSender::send_body(
+                    self.sender.as_ref(py).borrow(),
+                    more_body,
+                    body,
+                )?;
+
+                self.response_complete = !more_body;
+                inner
+            },
+            _ => return unexpected("of an unknown type"),
+        };
+
+        Ok(ASGISendAwaitable {
+            inner,
+            exchange: self.exchange.clone(),
+            completes_response: self.response_complete,
+        })
+    }
+}
+
+/// Extracts the `headers` of a `http.response.start` event.
+fn extract_headers(headers: &PyAny) -> PyResult<Headers> {
+    let mut out = Vec::new();
+
+    for pair in headers.iter()? {
+        let mut pair = pair?.iter()?;
+        let (name, value) = match (pair.next(), pair.next(), pair.next()) {
+            (Some(name), Some(value), None) => (name?, value?),
+            _ => {
+                return Err(PyValueError::new_err("headers must be (name, value) pairs"))
+            },
+        };
+
+        let name: Vec<u8> = name.extract()?;
+        let value: Vec<u8> = value.extract()?;
+
+        out.push((name, value));
+    }
+
+    Ok(out)
+}
+
+/// The awaitable returned by the ASGI `receive` callable.
+#[pyclass]
+pub struct ASGIReceiveAwaitable {
+    receiver: Py<DataReceiver>,
+
+    /// The awaitable receiving the next chunk of the body, `None` once
+    /// the body has been read.
+    inner: Option<ReceiveAwaitable>,
+    exchange: Arc<Exchange>,
+
+    /// The future yielded while waiting for the disconnect.
+    waiter: Option<PyObject>,
+}
+
+impl ASGIReceiveAwaitable {
+    /// Produces a `http.request` event.
+    fn request_event(
+        &self,
+        py: Python,
+        more_body: bool,
+        body: Py<PyBytes>,
+    ) -> PyResult<PyObject> {
+        if !more_body {
+            self.exchange
+                .request_complete
+                .store(true, Ordering::Relaxed);
+        }
+
+        let event = PyDict::new(py);
+        event.set_item("type", "http.request")?;
+        event.set_item("body", body)?;
+        event.set_item("more_body", more_body)?;
+        Ok(event.into())
+    }
+
+    /// Produces a `http.disconnect` event.
+    fn disconnect_event(&self, py: Python) -> PyResult<PyObject> {
+        let event = PyDict::new(py);
+        event.set_item("type", "http.disconnect")?;
+        Ok(event.into())
+    }
+}
+
+impl Awaitable for ASGIReceiveAwaitable {
+    type Output = PyObject;
+
+    fn waiter(&mut self) -> &mut Option<PyObject> {
+        &mut self.waiter
+    }
+
+    fn poll_ready(&mut self, py: Python) -> Poll<PyObject> {
+        if self.receiver.borrow(py).is_disconnected() {
+            return Ok(IterNextOutput::Return(self.disconnect_event(py)?));
+        }
+
+        if let Some(inner) = self.inner.as_mut() {
+            return Ok(match inner.poll(py)? {
+                IterNextOutput::Yield(fut) => IterNextOutput::Yield(fut),
+                IterNextOutput::Return((more_body, body)) => {
+                    IterNextOutput::Return(self.request_event(py, more_body, body)?)
+                },
+            });
+        }
+
+        if self.exchange.response_complete.load(Ordering::Relaxed) {
+            return Ok(IterNextOutput::Return(self.disconnect_event(py)?));
+        }
+
+        // Woken by either the response completing or the client
+        // disconnecting.
+        let (fut, waker) = create_waiter(py)?;
+        self.receiver.borrow(py).subscribe(waker.clone_ref(py));
+        self.exchange.disconnect_waiters.push(waker);
+
+        self.wait_on(py, fut)
+    }
+}
+
+#[pyproto]
+impl PyAsyncProtocol for ASGIReceiveAwaitable {
+    fn __await__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+}
+
+#[pyproto]
+impl PyIterProtocol for ASGIReceiveAwaitable {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __next__(slf: &PyCell<Self>) -> Poll<PyObject> {
+        advance(slf)
+    }
+}
+
+/// The awaitable returned by the ASGI `send` callable.
+#[pyclass]
+pub struct ASGISendAwaitable {
+    inner: SendAwaitable,
+    exchange: Arc<Exchange>,
+
+    /// If the event being sent is the end of the response.
+    completes_response: bool,
+}
+
+impl Awaitable for ASGISendAwaitable {
+    type Output = ();
+
+    fn waiter(&mut self) -> &mut Option<PyObject> {
+        self.inner.waiter()
+    }
+
+    fn poll_ready(&mut self, py: Python) -> Poll<()> {
+        let done = self.inner.poll_ready(py)?;
+        if let IterNextOutput::Return(()) = done {
+            if self.completes_response {
+                self.exchange.complete_response(py);
+            }
+        }
+
+        Ok(done)
+    }
+}
+
+#[pyproto]
+impl PyAsyncProtocol for ASGISendAwaitable {
+    fn __await__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+}
+
+#[pyproto]
+impl PyIterProtocol for ASGISendAwaitable {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __next__(slf: &PyCell<Self>) -> Poll<PyObject> {
+        advance(slf)
+    }
+}
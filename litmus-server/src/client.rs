@@ -1,19 +1,24 @@
+use std::net::SocketAddr;
 use std::time::Instant;
 
-use pyo3::PyResult;
+use pyo3::{PyObject, PyResult, Python};
+use tracing::{info_span, Span};
 
 use crate::event_loop::PreSetEventLoop;
-use crate::net::{SocketStatus, StreamHandle};
-use crate::protocols::{AutoProtocol, Protocols};
+use crate::health::SharedHealth;
+use crate::metrics::{Direction, SharedMetrics};
+use crate::net::{parse_proxy_header, ProxyStatus, SocketStatus, StreamHandle};
+use crate::protocols::{AutoProtocol, ConnectionState, Protocols};
 use crate::server::CallbackHandler;
 use crate::settings::Settings;
-use crate::traits::{BufferHandler, PollHandler, Reusable, SocketState};
+use crate::traits::{BaseTransport, BufferHandler, PollHandler, Reusable, SocketState};
 use crate::transport::Transport;
 
 pub struct ClientHandler {
     event_loop: PreSetEventLoop,
     connection: StreamHandle,
     settings: Settings,
+    metrics: SharedMetrics,
 
     protocol: AutoProtocol,
 
@@ -21,6 +26,141 @@ pub struct ClientHandler {
     is_idle: bool,
     last_time: Instant,
     idle_for: Instant,
+
+    /// The last observed stage of the protocol's request / response cycle.
+    state: ConnectionState,
+
+    /// When the protocol entered its current state.
+    state_since: Instant,
+
+    /// When data was last written to the socket.
+    last_write: Instant,
+
+    /// If the PROXY protocol header has not yet been read from the socket.
+    awaiting_proxy_header: bool,
+
+    /// The span covering the connection, entered whenever the handler is
+    /// polled so request spans are created within it.
+    span: Span,
+}
+
+/// Creates the span covering the lifetime of a connection.
+fn connection_span(event_loop: &PreSetEventLoop, connection: &StreamHandle) -> Span {
+    info_span!(
+        "connection",
+        conn = event_loop.index(),
+        client = %connection.addr,
+    )
+}
+
+/// Creates the transport the protocol controls the connection with, as if
+/// `client` had connected to `server`.
+fn connection_transport(
+    event_loop: &PreSetEventLoop,
+    connection: &StreamHandle,
+    client: SocketAddr,
+    server: SocketAddr,
+) -> Transport {
+    match connection.memory() {
+        Some(socket) => Transport::in_memory(
+            client,
+            server,
+            connection.tls,
+            socket.clone(),
+            event_loop.index(),
+        ),
+        None => Transport::new(client, server, connection.tls, event_loop.clone()),
+    }
+}
+
+impl ClientHandler {
+    /// If the connection is waiting for a new request with nothing left
+    /// to be written.
+    pub(crate) fn is_awaiting_request(&self) -> bool {
+        (self.protocol.state() == ConnectionState::Idle)
+            & !self.protocol.has_pending_writes()
+    }
+
+    /// Records any change in the protocol's state so timeouts can be
+    /// measured from the moment it started.
+    ///
+    /// The connection is still responding until the end of the response
+    /// has been written, so the write timeout applies to it and the keep
+    /// alive timeout only starts once the client has received it all.
+    fn update_state(&mut self) {
+        let state = match self.protocol.state() {
+            ConnectionState::Idle if self.protocol.has_pending_writes() => {
+                ConnectionState::Responding
+            },
+            state => state,
+        };
+        if state != self.state {
+            self.state = state;
+            self.state_since = Instant::now();
+        }
+    }
+
+    /// Marks the handler as idle and shuts down the connection, the slot
+    /// is released by the manager on the next keep alive tick.
+    fn release(&mut self) -> PyResult<()> {
+        self.is_idle = true;
+        self.idle_for = Instant::now();
+        self.shutdown()
+    }
+
+    /// Reads the PROXY protocol header from the start of the read buffer
+    /// and replaces the transport addresses with the ones it advertises.
+    ///
+    /// Returns true if the header has been consumed and the rest of the
+    /// buffer can be handed to the protocol.
+    fn read_proxy_header(&mut self) -> PyResult<bool> {
+        let buffer = self.protocol.read_buffer_acquire()?;
+        let (len, addrs) = match parse_proxy_header(buffer) {
+            ProxyStatus::Partial => return Ok(false),
+            ProxyStatus::Complete { len, addrs } => (len, addrs),
+            ProxyStatus::Invalid => {
+                debug!(
+                    conn = self.event_loop.index(),
+                    client:% = self.connection.addr;
+                    "closing connection to {}, invalid PROXY protocol header",
+                    self.connection.addr
+                );
+                self.release()?;
+                return Ok(false);
+            },
+        };
+
+        let _ = buffer.split_to(len);
+        self.awaiting_proxy_header = false;
+
+        if let Some((client, server)) = addrs {
+            self.span.record("client", tracing::field::display(client));
+            let transport =
+                connection_transport(&self.event_loop, &self.connection, client, server);
+            self.protocol.new_connection(transport);
+        }
+
+        Ok(true)
+    }
+
+    /// Writes a `408 Request Timeout` to the socket without waiting for it
+    /// to become writable and closes the connection.
+    fn request_timed_out(&mut self) -> PyResult<()> {
+        debug!(
+            conn = self.event_loop.index(),
+            client:% = self.connection.addr;
+            "request from {} timed out while in state {:?}",
+            self.connection.addr, self.state
+        );
+
+        self.protocol.request_timed_out()?;
+        let buffer = self.protocol.write_buffer_acquire()?;
+
+        // The client is most likely not reading so this is best effort.
+        let _ = self.connection.write(buffer);
+
+        self.release()
+    }
 }
 
 impl Reusable for ClientHandler {
@@ -29,51 +169,52 @@ impl Reusable for ClientHandler {
         event_loop: PreSetEventLoop,
         connection: StreamHandle,
         settings: Settings,
+        metrics: SharedMetrics,
+        health: SharedHealth,
     ) -> PyResult<Self> {
-        event_loop.add_reader()?;
-
-        let transport = Transport::new(
+        let transport = connection_transport(
+            &event_loop,
+            &connection,
             connection.addr,
             connection.server,
-            connection.tls,
-            event_loop.clone(),
         );
+        transport.resume_reading()?;
 
-        let protocol =
-            AutoProtocol::new(settings.clone(), Protocols::H1, transport, callback);
+        let protocol = AutoProtocol::new(
+            settings.clone(),
+            Protocols::H1,
+            transport,
+            callback,
+            metrics.clone(),
+            health,
+        );
+        let awaiting_proxy_header = connection.proxy_protocol;
+        let span = connection_span(&event_loop, &connection);
 
         Ok(Self {
             event_loop,
             connection,
             settings,
+            metrics,
             protocol,
 
             is_free: false,
             is_idle: false,
             last_time: Instant::now(),
             idle_for: Instant::now(),
-        })
-    }
 
-    fn set_connection(&mut self, connection: StreamHandle) -> PyResult<()> {
-        self.event_loop.set_fd(connection.fd());
-        self.connection = connection;
-
-        let transport = Transport::new(
-            self.connection.addr,
-            self.connection.server,
-            self.connection.tls,
-            self.event_loop.clone(),
-        );
-        self.protocol.new_connection(transport);
-        self.event_loop.add_reader()?;
-
-        Ok(())
+            state: ConnectionState::Idle,
+            state_since: Instant::now(),
+            last_write: Instant::now(),
+            awaiting_proxy_header,
+            span,
+        })
     }
 }
 
 impl PollHandler for ClientHandler {
     fn poll_read(&mut self) -> PyResult<()> {
+        let _span = self.span.clone().entered();
         let buffer = self.protocol.read_buffer_acquire()?;
 
         let len = match self.connection.read(buffer)? {
@@ -81,21 +222,30 @@ impl PollHandler for ClientHandler {
             SocketStatus::Complete(len) => len,
             SocketStatus::Disconnect => {
                 self.protocol.connection_lost()?;
-                self.is_idle = true;
-                self.idle_for = Instant::now();
-                return self.shutdown();
+                return self.release();
             },
         };
 
-        // EOF
+        self.metrics.bytes_received(len);
+
+        // EOF, the client has closed the connection so its slot is freed
+        // for the next one rather than held until a timeout.
         if len == 0 {
+            self.connection.close();
             self.protocol.eof_received()?;
+            self.is_idle = true;
+            self.idle_for = Instant::now();
+            return Ok(());
+        }
+
+        if self.awaiting_proxy_header && !self.read_proxy_header()? {
             return Ok(());
         }
 
         self.protocol.read_buffer_filled(len)?;
 
         self.last_time = Instant::now();
+        self.update_state();
 
         self.protocol.maybe_switch()?;
 
@@ -103,41 +253,109 @@ impl PollHandler for ClientHandler {
     }
 
     fn poll_write(&mut self) -> PyResult<()> {
+        let _span = self.span.clone().entered();
+        if !self.protocol.has_pending_writes() {
+            // A new batch of data is about to be written so the write
+            // timeout starts from now.
+            self.last_write = Instant::now();
+        }
+
         let buffer = self.protocol.write_buffer_acquire()?;
+        let pending = buffer.len();
 
         let len = match self.connection.write(buffer)? {
-            SocketStatus::WouldBlock => return Ok(()),
+            SocketStatus::WouldBlock => {
+                self.metrics.backpressure(Direction::Write);
+                return Ok(());
+            },
             SocketStatus::Complete(len) => len,
             SocketStatus::Disconnect => {
                 self.protocol.connection_lost()?;
-                self.is_idle = true;
-                self.idle_for = Instant::now();
-                return self.shutdown();
+                return self.release();
             },
         };
 
+        if len > 0 {
+            self.last_write = Instant::now();
+        }
+
+        // The socket's send buffer is full, the rest is written once the
+        // client catches up.
+        if len < pending {
+            self.metrics.backpressure(Direction::Write);
+        }
+        self.metrics.bytes_sent(len);
+
         self.protocol.write_buffer_drained(len)?;
+        self.update_state();
 
         Ok(())
     }
 
     fn poll_close(&mut self) -> PyResult<()> {
+        let _span = self.span.clone().entered();
         self.connection.close();
         self.protocol.connection_lost()?;
+        self.is_idle = true;
+        self.idle_for = Instant::now();
         Ok(())
     }
 
     fn poll_keep_alive(&mut self) -> PyResult<()> {
-        if self.last_time.elapsed() >= self.settings.keep_alive {
-            self.connection.close();
-            self.is_idle = true;
-            self.idle_for = Instant::now();
-            return self.shutdown();
+        let _span = self.span.clone().entered();
+        match self.state {
+            ConnectionState::Idle
+                if self.state_since.elapsed() >= self.settings.keep_alive =>
+            {
+                self.metrics.keep_alive_close();
+                self.release()
+            },
+            ConnectionState::ReadingHead
+                if self.state_since.elapsed() >= self.settings.header_timeout =>
+            {
+                self.request_timed_out()
+            },
+            ConnectionState::ReadingBody if self.protocol.is_body_paused() => {
+                // The application is behind rather than the client so the
+                // body timeout starts again once reading resumes.
+                self.last_time = Instant::now();
+                Ok(())
+            },
+            ConnectionState::ReadingBody
+                if self.last_time.elapsed() >= self.settings.body_timeout =>
+            {
+                self.request_timed_out()
+            },
+            ConnectionState::Responding
+                if self.protocol.has_pending_writes()
+                    & (self.last_write.elapsed() >= self.settings.write_timeout) =>
+            {
+                debug!(
+                    conn = self.event_loop.index(),
+                    client:% = self.connection.addr;
+                    "closing connection to {}, client stopped reading",
+                    self.connection.addr
+                );
+                self.release()
+            },
+            ConnectionState::Responding
+                if !self.protocol.has_pending_writes()
+                    && Python::with_gil(|py| self.protocol.response_abandoned(py)) =>
+            {
+                debug!(
+                    conn = self.event_loop.index(),
+                    client:% = self.connection.addr;
+                    "application finished without completing the response to {}",
+                    self.connection.addr
+                );
+                self.protocol.abandon_response()
+            },
+            _ => Ok(()),
         }
-        Ok(())
     }
 
     fn shutdown(&mut self) -> PyResult<()> {
+        let _span = self.span.clone().entered();
         self.connection.close();
         self.protocol.connection_lost()?;
         Ok(())
@@ -154,4 +372,8 @@ impl PollHandler for ClientHandler {
     fn set_free(&mut self) {
         self.is_free = true;
     }
+
+    fn pending_task(&self, py: Python) -> Option<PyObject> {
+        self.protocol.pending_task(py)
+    }
 }
@@ -11,6 +11,14 @@ pub type SocketFd = u64;
 #[cfg(unix)]
 pub type SocketFd = i32;
 
+/// The descriptor given to connections without a socket.
+#[cfg(windows)]
+pub const NO_SOCKET: SocketFd = SocketFd::MAX;
+
+/// The descriptor given to connections without a socket.
+#[cfg(unix)]
+pub const NO_SOCKET: SocketFd = -1;
+
 #[derive(Clone)]
 pub struct EventLoop {
     add_reader: CheapPyObject,
@@ -130,6 +138,11 @@ impl PreSetEventLoop {
         self.is_writing.load(Ordering::Relaxed)
     }
 
+    /// The index of the handler the event loop invokes.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
     pub fn close_socket(&self) -> PyResult<()> {
         self.event_loop.close_socket(self.index)
     }
@@ -0,0 +1,462 @@
+use std::net::{IpAddr, SocketAddr};
+use std::str::{self, FromStr};
+
+use httparse::Header;
+
+const FORWARDED: &str = "forwarded";
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+
+/// A simple tuple containing the host string and port.
+type SocketDetails = (String, u16);
+
+/// A block of IP addresses in CIDR notation e.g. `10.0.0.0/8`.
+///
+/// A single address without a prefix length is treated as a block
+/// containing only that address.
+#[derive(Debug, Copy, Clone)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    /// If the given address is within this network.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, normalise(addr)) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                (u32::from(net) & mask) == (u32::from(addr) & mask)
+            },
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                (u128::from(net) & mask) == (u128::from(addr) & mask)
+            },
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid ip network {:?}", s);
+
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+
+        let addr = normalise(addr.trim().parse().map_err(|_| invalid())?);
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.trim().parse::<u8>().map_err(|_| invalid())?,
+            None => max,
+        };
+
+        if prefix > max {
+            return Err(invalid());
+        }
+
+        Ok(Self { addr, prefix })
+    }
+}
+
+/// Converts IPv4-mapped IPv6 addresses back to IPv4 so they are matched
+/// against IPv4 networks.
+fn normalise(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => addr,
+        },
+        addr => addr,
+    }
+}
+
+/// If the given address is within any of the trusted networks.
+pub fn is_trusted(trusted: &[IpNetwork], addr: IpAddr) -> bool {
+    trusted.iter().any(|net| net.contains(addr))
+}
+
+/// The connection details a trusted proxy has reported for the original
+/// client.
+#[derive(Default)]
+pub struct ForwardedDetails {
+    /// The address of the original client.
+    pub client: Option<SocketDetails>,
+
+    /// The scheme the original client used.
+    pub scheme: Option<&'static str>,
+
+    /// The host the original client requested.
+    pub server: Option<SocketDetails>,
+}
+
+/// Collects the proxy headers of a request so they can be resolved once
+/// all the headers have been seen.
+#[derive(Default)]
+pub struct ForwardedHeaders<'a> {
+    forwarded: Vec<&'a str>,
+    x_forwarded_for: Vec<&'a str>,
+    x_forwarded_proto: Option<&'a str>,
+    x_forwarded_host: Option<&'a str>,
+}
+
+impl<'a> ForwardedHeaders<'a> {
+    /// Checks if the header is a proxy header and stores it if it is.
+    pub fn check_header(&mut self, header: &Header<'a>) {
+        let value = match str::from_utf8(header.value) {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+
+        if header.name.eq_ignore_ascii_case(FORWARDED) {
+            self.forwarded.push(value);
+        } else if header.name.eq_ignore_ascii_case(X_FORWARDED_FOR) {
+            self.x_forwarded_for.push(value);
+        } else if header.name.eq_ignore_ascii_case(X_FORWARDED_PROTO) {
+            self.x_forwarded_proto = Some(value);
+        } else if header.name.eq_ignore_ascii_case(X_FORWARDED_HOST) {
+            self.x_forwarded_host = Some(value);
+        }
+    }
+
+    /// Works out the original client's details from the collected headers.
+    ///
+    /// The list of hops is walked from the closest proxy outwards skipping
+    /// any trusted proxies, the first untrusted hop is the client. The
+    /// `Forwarded` header takes priority over the `X-Forwarded-*` headers
+    /// if both are present.
+    pub fn resolve(&self, trusted: &[IpNetwork]) -> ForwardedDetails {
+        if !self.forwarded.is_empty() {
+            return self.resolve_forwarded(trusted);
+        }
+
+        let mut details = ForwardedDetails::default();
+
+        let hops: Vec<&str> = self
+            .x_forwarded_for
+            .iter()
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .collect();
+        if let Some(hop) = select_hop(&hops, |hop| *hop, trusted) {
+            details.client = parse_node(hop);
+        }
+
+        // Only the value set by the closest proxy can be relied on.
+        details.scheme = self
+            .x_forwarded_proto
+            .and_then(|v| v.rsplit(',').next())
+            .and_then(parse_scheme);
+
+        let scheme = details.scheme;
+        details.server = self
+            .x_forwarded_host
+            .and_then(|v| v.rsplit(',').next())
+            .and_then(|host| parse_host(host, scheme));
+
+        details
+    }
+
+    /// Resolves the details from the standard `Forwarded` header.
+    fn resolve_forwarded(&self, trusted: &[IpNetwork]) -> ForwardedDetails {
+        let elements: Vec<Vec<(&str, &str)>> = self
+            .forwarded
+            .iter()
+            .flat_map(|v| v.split(','))
+            .map(parse_element)
+            .collect();
+
+        let mut details = ForwardedDetails::default();
+
+        let element = select_hop(
+            &elements,
+            |pairs| get_param(pairs, "for").unwrap_or(""),
+            trusted,
+        );
+        if let Some(pairs) = element {
+            details.client = get_param(pairs, "for").and_then(parse_node);
+            details.scheme = get_param(pairs, "proto").and_then(parse_scheme);
+
+            let scheme = details.scheme;
+            details.server =
+                get_param(pairs, "host").and_then(|host| parse_host(host, scheme));
+        }
+
+        details
+    }
+}
+
+/// Walks the hops from right to left returning the first one that is not
+/// a trusted proxy, or the furthest hop if every one of them is trusted.
+fn select_hop<'b, T>(
+    hops: &'b [T],
+    node: impl Fn(&T) -> &str,
+    trusted: &[IpNetwork],
+) -> Option<&'b T> {
+    for hop in hops.iter().rev() {
+        let is_trusted = parse_node(node(hop))
+            .and_then(|(host, _)| host.parse::<IpAddr>().ok())
+            .map(|ip| is_trusted(trusted, ip))
+            .unwrap_or(false);
+
+        if !is_trusted {
+            return Some(hop);
+        }
+    }
+
+    hops.first()
+}
+
+/// Splits a single `Forwarded` element into its `name=value` pairs.
+fn parse_element(element: &str) -> Vec<(&str, &str)> {
+    element
+        .split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(name, value)| (name.trim(), value.trim().trim_matches('"')))
+        .collect()
+}
+
+/// Gets the value of the first pair with the given name.
+fn get_param<'b>(pairs: &[(&str, &'b str)], name: &str) -> Option<&'b str> {
+    pairs
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| *v)
+}
+
+/// Parses a node identifier, either a plain IP or a `host:port` pair with
+/// IPv6 addresses enclosed in brackets.
+///
+/// Obfuscated identifiers and `unknown` are not addresses and are ignored.
+fn parse_node(node: &str) -> Option<SocketDetails> {
+    let node = node.trim().trim_matches('"');
+
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some((ip.to_string(), 0));
+    }
+
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some((addr.ip().to_string(), addr.port()));
+    }
+
+    // A bracketed IPv6 address without a port.
+    let ip = node
+        .strip_prefix('[')?
+        .strip_suffix(']')?
+        .parse::<IpAddr>()
+        .ok()?;
+    Some((ip.to_string(), 0))
+}
+
+/// Parses a scheme, only `http` and `https` are accepted.
+fn parse_scheme(scheme: &str) -> Option<&'static str> {
+    let scheme = scheme.trim();
+    if scheme.eq_ignore_ascii_case("https") {
+        Some("https")
+    } else if scheme.eq_ignore_ascii_case("http") {
+        Some("http")
+    } else {
+        None
+    }
+}
+
+/// Parses a host header value into a host and port, using the default port
+/// of the scheme if one is not given.
+fn parse_host(host: &str, scheme: Option<&str>) -> Option<SocketDetails> {
+    let host = host.trim();
+    if host.is_empty() {
+        return None;
+    }
+
+    let default_port = if scheme == Some("https") { 443 } else { 80 };
+
+    // IPv6 addresses are enclosed in brackets as they contain colons.
+    let port_start = match host.rfind(']') {
+        Some(end) => host[end..].find(':').map(|i| end + i),
+        None => host.rfind(':'),
+    };
+
+    match port_start {
+        Some(index) => {
+            let port = host[index + 1..].parse().ok()?;
+            Some((host[..index].to_string(), port))
+        },
+        None => Some((host.to_string(), default_port)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn networks(networks: &[&str]) -> Vec<IpNetwork> {
+        networks.iter().map(|n| n.parse().unwrap()).collect()
+    }
+
+    fn resolve(headers: &[(&str, &[u8])], trusted: &[&str]) -> ForwardedDetails {
+        let headers: Vec<Header> = headers
+            .iter()
+            .map(|(name, value)| Header { name, value })
+            .collect();
+
+        let mut forwarded = ForwardedHeaders::default();
+        for header in headers.iter() {
+            forwarded.check_header(header);
+        }
+
+        forwarded.resolve(&networks(trusted))
+    }
+
+    fn client(host: &str, port: u16) -> Option<SocketDetails> {
+        Some((host.to_string(), port))
+    }
+
+    #[test]
+    fn networks_are_parsed() {
+        let net: IpNetwork = "10.0.0.0/8".parse().unwrap();
+        assert!(net.contains("10.1.2.3".parse().unwrap()));
+        assert!(!net.contains("11.0.0.1".parse().unwrap()));
+
+        let net: IpNetwork = "192.168.1.7".parse().unwrap();
+        assert!(net.contains("192.168.1.7".parse().unwrap()));
+        assert!(!net.contains("192.168.1.8".parse().unwrap()));
+
+        let net: IpNetwork = "2001:db8::/32".parse().unwrap();
+        assert!(net.contains("2001:db8:1::1".parse().unwrap()));
+        assert!(!net.contains("2001:db9::1".parse().unwrap()));
+        assert!(!net.contains("10.0.0.1".parse().unwrap()));
+
+        let net: IpNetwork = "0.0.0.0/0".parse().unwrap();
+        assert!(net.contains("203.0.113.9".parse().unwrap()));
+    }
+
+    #[test]
+    fn invalid_networks_are_rejected() {
+        assert!("10.0.0.0/33".parse::<IpNetwork>().is_err());
+        assert!("::/129".parse::<IpNetwork>().is_err());
+        assert!("10.0.0.0/x".parse::<IpNetwork>().is_err());
+        assert!("example.com".parse::<IpNetwork>().is_err());
+    }
+
+    #[test]
+    fn mapped_addresses_match_ipv4_networks() {
+        let net: IpNetwork = "10.0.0.0/8".parse().unwrap();
+        assert!(net.contains("::ffff:10.0.0.1".parse().unwrap()));
+        assert!(!net.contains("::ffff:11.0.0.1".parse().unwrap()));
+
+        let net: IpNetwork = "::ffff:10.0.0.1".parse().unwrap();
+        assert!(net.contains("10.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn trusted_hops_are_skipped() {
+        let details = resolve(
+            &[
+                ("X-Forwarded-For", b"198.51.100.1, 203.0.113.7"),
+                ("x-forwarded-for", b"10.0.0.2"),
+            ],
+            &["10.0.0.0/8"],
+        );
+        assert_eq!(details.client, client("203.0.113.7", 0));
+    }
+
+    #[test]
+    fn the_furthest_hop_is_used_when_every_hop_is_trusted() {
+        let details = resolve(
+            &[("x-forwarded-for", b"10.0.0.3, 10.0.0.2")],
+            &["10.0.0.0/8"],
+        );
+        assert_eq!(details.client, client("10.0.0.3", 0));
+    }
+
+    #[test]
+    fn only_the_closest_proto_and_host_are_used() {
+        let details = resolve(
+            &[
+                ("x-forwarded-proto", b"http, https"),
+                ("x-forwarded-host", b"evil.example, example.com"),
+            ],
+            &[],
+        );
+        assert_eq!(details.scheme, Some("https"));
+        assert_eq!(details.server, client("example.com", 443));
+        assert_eq!(details.client, None);
+    }
+
+    #[test]
+    fn unknown_schemes_are_ignored() {
+        let details = resolve(&[("x-forwarded-proto", b"gopher")], &[]);
+        assert_eq!(details.scheme, None);
+    }
+
+    #[test]
+    fn forwarded_takes_priority() {
+        let details = resolve(
+            &[
+                ("x-forwarded-for", b"198.51.100.1"),
+                (
+                    "Forwarded",
+                    b"for=192.0.2.60;proto=http, for=\"[2001:db8::1]:4711\";proto=https;host=example.com",
+                ),
+            ],
+            &[],
+        );
+        assert_eq!(details.client, client("2001:db8::1", 4711));
+        assert_eq!(details.scheme, Some("https"));
+        assert_eq!(details.server, client("example.com", 443));
+    }
+
+    #[test]
+    fn forwarded_skips_trusted_elements() {
+        let details = resolve(
+            &[(
+                "forwarded",
+                b"for=192.0.2.60;proto=https, for=10.0.0.2;proto=http",
+            )],
+            &["10.0.0.0/8"],
+        );
+        assert_eq!(details.client, client("192.0.2.60", 0));
+        assert_eq!(details.scheme, Some("https"));
+    }
+
+    #[test]
+    fn obfuscated_nodes_are_not_addresses() {
+        let details = resolve(&[("forwarded", b"for=_hidden, for=unknown")], &[]);
+        assert_eq!(details.client, None);
+    }
+
+    #[test]
+    fn nodes_are_parsed() {
+        assert_eq!(parse_node("192.0.2.1"), client("192.0.2.1", 0));
+        assert_eq!(parse_node("192.0.2.1:8080"), client("192.0.2.1", 8080));
+        assert_eq!(parse_node("[2001:db8::1]"), client("2001:db8::1", 0));
+        assert_eq!(
+            parse_node("\"[2001:db8::1]:80\""),
+            client("2001:db8::1", 80)
+        );
+        assert_eq!(parse_node("unknown"), None);
+    }
+
+    #[test]
+    fn hosts_are_parsed() {
+        assert_eq!(parse_host("example.com", None), client("example.com", 80));
+        assert_eq!(
+            parse_host("example.com", Some("https")),
+            client("example.com", 443)
+        );
+        assert_eq!(
+            parse_host("example.com:8080", None),
+            client("example.com", 8080)
+        );
+        assert_eq!(parse_host("[::1]:8080", None), client("[::1]", 8080));
+        assert_eq!(parse_host("[::1]", Some("https")), client("[::1]", 443));
+        assert_eq!(parse_host("example.com:http", None), None);
+        assert_eq!(parse_host(" ", None), None);
+    }
+}
@@ -0,0 +1,149 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use http::StatusCode;
+use slab::Slab;
+
+/// A cheaply cloneable handle to the server's health.
+pub(crate) type SharedHealth = Arc<Health>;
+
+/// A request currently being handled by the application.
+pub(crate) struct InFlight {
+    pub conn: usize,
+    pub client: SocketAddr,
+    pub method: String,
+    pub path: String,
+    pub request_id: String,
+    pub started: Instant,
+}
+
+/// The state reported by the health and readiness endpoints and watched
+/// by the stall detector.
+pub(crate) struct Health {
+    /// The point heartbeats are measured from.
+    created: Instant,
+
+    /// Milliseconds since `created` the event loop last checked in plus
+    /// one, zero if it has not checked in yet.
+    last_heartbeat: AtomicU64,
+
+    /// If the application has started and the server is not draining.
+    ready: AtomicBool,
+
+    /// The requests currently being handled by the application.
+    in_flight: Mutex<Slab<InFlight>>,
+}
+
+impl Health {
+    pub(crate) fn new() -> Self {
+        Self {
+            created: Instant::now(),
+            last_heartbeat: AtomicU64::new(0),
+            ready: AtomicBool::new(false),
+            in_flight: Mutex::new(Slab::new()),
+        }
+    }
+
+    /// Records that the event loop is still running callbacks on time.
+    pub(crate) fn heartbeat(&self) {
+        let now = self.created.elapsed().as_millis() as u64;
+        self.last_heartbeat.store(now + 1, Ordering::Relaxed);
+    }
+
+    /// The time since the event loop last checked in, `None` if it has not
+    /// checked in yet.
+    pub(crate) fn since_heartbeat(&self) -> Option<Duration> {
+        match self.last_heartbeat.load(Ordering::Relaxed) {
+            0 => None,
+            last => {
+                let last = Duration::from_millis(last - 1);
+                Some(self.created.elapsed().saturating_sub(last))
+            },
+        }
+    }
+
+    pub(crate) fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::Relaxed);
+    }
+
+    /// The response to a liveness probe, failing once the event loop has
+    /// missed heartbeats for longer than the timeout.
+    pub(crate) fn liveness(&self, timeout: Duration) -> (StatusCode, &'static str) {
+        match self.since_heartbeat() {
+            Some(since) if since > timeout => {
+                (StatusCode::SERVICE_UNAVAILABLE, "event loop stalled")
+            },
+            _ => (StatusCode::OK, "ok"),
+        }
+    }
+
+    /// The response to a readiness probe.
+    pub(crate) fn readiness(&self) -> (StatusCode, &'static str) {
+        if self.ready.load(Ordering::Relaxed) {
+            (StatusCode::OK, "ready")
+        } else {
+            (StatusCode::SERVICE_UNAVAILABLE, "not ready")
+        }
+    }
+
+    /// Registers a request handed to the application, returning the key
+    /// it is removed with once it completes.
+    pub(crate) fn request_started(&self, request: InFlight) -> usize {
+        self.in_flight.lock().unwrap().insert(request)
+    }
+
+    pub(crate) fn request_finished(&self, key: usize) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if in_flight.contains(key) {
+            in_flight.remove(key);
+        }
+    }
+
+    /// Describes the requests currently being handled, oldest first.
+    pub(crate) fn describe_in_flight(&self) -> Vec<String> {
+        let in_flight = self.in_flight.lock().unwrap();
+        let mut requests: Vec<&InFlight> = in_flight.iter().map(|(_, r)| r).collect();
+        requests.sort_by_key(|r| r.started);
+
+        requests
+            .into_iter()
+            .map(|r| {
+                format!(
+                    "{} {} (request {}, conn {}, client {}, running for {:?})",
+                    r.method,
+                    r.path,
+                    r.request_id,
+                    r.conn,
+                    r.client,
+                    r.started.elapsed(),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Answers the probe endpoints from the metrics thread, this way a stalled
+/// event loop is reported as failing rather than leaving the probe hanging.
+pub(crate) struct Probes {
+    pub health: SharedHealth,
+    pub health_path: Option<String>,
+    pub ready_path: Option<String>,
+    pub heartbeat_timeout: Duration,
+}
+
+impl Probes {
+    /// The response to the probe at the given path, `None` if the path is
+    /// not a probe.
+    pub(crate) fn answer(&self, path: &str) -> Option<(StatusCode, &'static str)> {
+        let is_path = |probe: &Option<String>| probe.as_deref() == Some(path);
+        if is_path(&self.health_path) {
+            Some(self.health.liveness(self.heartbeat_timeout))
+        } else if is_path(&self.ready_path) {
+            Some(self.health.readiness())
+        } else {
+            None
+        }
+    }
+}
@@ -1,14 +1,23 @@
 #[macro_use]
 extern crate log;
 
+mod access_log;
+pub mod asgi;
 mod client;
 mod event_loop;
+mod forwarded;
+mod health;
+mod lifespan;
+mod lsgi;
 mod manager;
+mod metrics;
 mod net;
 mod protocols;
-mod lsgi;
 pub mod responders;
 pub mod server;
 pub mod settings;
+pub mod testing;
 mod traits;
 mod transport;
+mod watchdog;
+pub mod wsgi;
@@ -0,0 +1,471 @@
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+use pyo3::class::iter::IterNextOutput;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use pyo3::{PyAsyncProtocol, PyIterProtocol};
+
+use crate::responders::{advance, create_waiter, Awaitable, Poll};
+use crate::server::CallbackHandler;
+
+/// The type of the lifespan scope.
+const SCOPE_TYPE: &str = "lifespan";
+
+/// When the application's lifespan hooks are run.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LifespanMode {
+    /// Run the hooks if the application supports them.
+    Auto,
+
+    /// Run the hooks, failing startup if the application does not
+    /// support them.
+    On,
+
+    /// Never run the hooks.
+    Off,
+}
+
+impl FromStr for LifespanMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "on" => Ok(Self::On),
+            "off" => Ok(Self::Off),
+            _ => Err(format!(
+                "invalid lifespan mode {:?} expected one of 'auto', 'on' or 'off'",
+                s
+            )),
+        }
+    }
+}
+
+/// A stage of the lifespan the server waits on the application for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Phase {
+    Startup,
+    Shutdown,
+}
+
+impl Phase {
+    /// The event sent to the application to begin the phase.
+    fn event(self) -> &'static str {
+        match self {
+            Self::Startup => "lifespan.startup",
+            Self::Shutdown => "lifespan.shutdown",
+        }
+    }
+}
+
+/// The application's answer to a phase.
+enum Reply {
+    Complete,
+    Failed(String),
+}
+
+/// The state shared between the server and the lifespan handles given to
+/// the application.
+#[pyclass]
+pub struct LifespanState {
+    /// Events waiting to be received by the application.
+    events: VecDeque<&'static str>,
+
+    /// Wakers waiting for an event to be received.
+    receive_waiters: Vec<PyObject>,
+
+    /// The phase the server is waiting on the application to complete.
+    phase: Option<Phase>,
+
+    /// The application's answer to the current phase.
+    reply: Option<Reply>,
+
+    /// Wakers waiting for the application's answer.
+    reply_waiters: Vec<PyObject>,
+
+    /// If the application supports the lifespan protocol, this is only
+    /// known for certain once startup has completed.
+    supported: bool,
+}
+
+impl LifespanState {
+    /// Starts the given phase sending its event to the application.
+    fn begin(&mut self, py: Python, phase: Phase) {
+        self.phase = Some(phase);
+        self.reply = None;
+        self.events.push_back(phase.event());
+        wake(py, &mut self.receive_waiters);
+    }
+}
+
+/// Invokes and removes every waker, any errors raised by the wakers are
+/// ignored.
+fn wake(py: Python, wakers: &mut Vec<PyObject>) {
+    for waker in wakers.drain(..) {
+        let _ = waker.call0(py);
+    }
+}
+
+/// Runs the application's lifespan scope.
+pub(crate) struct Lifespan {
+    mode: LifespanMode,
+
+    /// The state shared with the application once the scope has started.
+    state: Option<Py<LifespanState>>,
+
+    /// The task running the application's lifespan scope.
+    task: Option<PyObject>,
+}
+
+impl Lifespan {
+    pub(crate) fn new(mode: LifespanMode) -> Self {
+        Self {
+            mode,
+            state: None,
+            task: None,
+        }
+    }
+
+    /// Invokes the callback with the lifespan scope and sends the startup
+    /// event, returning an awaitable which completes once the application
+    /// has started.
+    pub(crate) fn startup(
+        &mut self,
+        py: Python,
+        callback: &CallbackHandler,
+    ) -> PyResult<PyObject> {
+        if (self.mode == LifespanMode::Off) | self.state.is_some() {
+            return Ok(Py::new(py, CompletedAwaitable)?.into_py(py));
+        }
+
+        let state = Py::new(
+            py,
+            LifespanState {
+                events: VecDeque::new(),
+                receive_waiters: Vec::new(),
+                phase: None,
+                reply: None,
+                reply_waiters: Vec::new(),
+                supported: true,
+            },
+        )?;
+
+        let scope = PyDict::new(py);
+        scope.set_item("type", SCOPE_TYPE)?;
+
+        let sender = LifespanSender {
+            state: state.clone_ref(py),
+        };
+        let receiver = LifespanReceiver {
+            state: state.clone_ref(py),
+        };
+        let task = callback.invoke((scope, sender, receiver))?;
+
+        state.borrow_mut(py).begin(py, Phase::Startup);
+        self.state = Some(state.clone_ref(py));
+        self.task = Some(task.clone_ref(py));
+
+        let waiter = PhaseAwaitable {
+            state,
+            task,
+            phase: Phase::Startup,
+            mode: self.mode,
+            waiter: None,
+        };
+
+        Ok(Py::new(py, waiter)?.into_py(py))
+    }
+
+    /// Sends the shutdown event, returning an awaitable which completes
+    /// once the application has shut down.
+    pub(crate) fn shutdown(&mut self, py: Python) -> PyResult<PyObject> {
+        let (state, task) = match (self.state.as_ref(), self.task.take()) {
+            (Some(state), Some(task)) if state.borrow(py).supported => {
+                (state.clone_ref(py), task)
+            },
+            _ => return Ok(Py::new(py, CompletedAwaitable)?.into_py(py)),
+        };
+
+        state.borrow_mut(py).begin(py, Phase::Shutdown);
+
+        let waiter = PhaseAwaitable {
+            state,
+            task,
+            phase: Phase::Shutdown,
+            mode: self.mode,
+            waiter: None,
+        };
+
+        Ok(Py::new(py, waiter)?.into_py(py))
+    }
+}
+
+/// The `receive` callable given to the application's lifespan scope.
+#[pyclass]
+pub struct LifespanReceiver {
+    state: Py<LifespanState>,
+}
+
+#[pymethods]
+impl LifespanReceiver {
+    /// Waits for the next lifespan event.
+    ///
+    /// Returns:
+    ///     An awaitable resolving to a `lifespan.startup` or
+    ///     `lifespan.shutdown` event.
+    #[call]
+    fn __call__(&self, py: Python) -> LifespanReceiveAwaitable {
+        LifespanReceiveAwaitable {
+            state: self.state.clone_ref(py),
+            waiter: None,
+        }
+    }
+}
+
+/// The `send` callable given to the application's lifespan scope.
+#[pyclass]
+pub struct LifespanSender {
+    state: Py<LifespanState>,
+}
+
+#[pymethods]
+impl LifespanSender {
+    /// Reports the outcome of the current lifespan phase.
+    ///
+    /// Accepts the `lifespan.startup.complete`, `lifespan.startup.failed`,
+    /// `lifespan.shutdown.complete` and `lifespan.shutdown.failed` events,
+    /// the failed events may have a `message` explaining the failure.
+    ///
+    /// Returns:
+    ///     An awaitable which completes immediately.
+    ///
+    /// Raises:
+    ///     RuntimeError:
+    ///         If the event does not belong to the current phase.
+    #[call]
+    fn __call__(&self, py: Python, message: &PyDict) -> PyResult<CompletedAwaitable> {
+        let message_type: &str = match message.get_item("type") {
+            Some(t) => t.extract()?,
+            None => return Err(PyValueError::new_err("message is missing a type")),
+        };
+
+        let mut state = self.state.borrow_mut(py);
+        let (phase, reply) = match message_type {
+            "lifespan.startup.complete" => (Phase::Startup, Reply::Complete),
+            "lifespan.shutdown.complete" => (Phase::Shutdown, Reply::Complete),
+            "lifespan.startup.failed" | "lifespan.shutdown.failed" => {
+                let phase = if message_type == "lifespan.startup.failed" {
+                    Phase::Startup
+                } else {
+                    Phase::Shutdown
+                };
+
+                let reason: String = match message.get_item("message") {
+                    Some(m) => m.extract()?,
+                    None => String::new(),
+                };
+
+                (phase, Reply::Failed(reason))
+            },
+            _ => {
+                return Err(PyRuntimeError::new_err(format!(
+                    "unexpected lifespan message {:?}",
+                    message_type
+                )))
+            },
+        };
+
+        if (state.phase != Some(phase)) | state.reply.is_some() {
+            return Err(PyRuntimeError::new_err(format!(
+                "unexpected lifespan message {:?} sent outside of {}",
+                message_type,
+                phase.event(),
+            )));
+        }
+
+        state.reply = Some(reply);
+        wake(py, &mut state.reply_waiters);
+
+        Ok(CompletedAwaitable)
+    }
+}
+
+/// An awaitable which completes immediately.
+#[pyclass]
+pub struct CompletedAwaitable;
+
+#[pyproto]
+impl PyAsyncProtocol for CompletedAwaitable {
+    fn __await__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+}
+
+#[pyproto]
+impl PyIterProtocol for CompletedAwaitable {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __next__(_slf: PyRefMut<Self>) -> IterNextOutput<PyObject, ()> {
+        IterNextOutput::Return(())
+    }
+}
+
+/// The awaitable returned by the lifespan `receive` callable.
+#[pyclass]
+pub struct LifespanReceiveAwaitable {
+    state: Py<LifespanState>,
+
+    /// The future yielded while waiting for an event.
+    waiter: Option<PyObject>,
+}
+
+impl Awaitable for LifespanReceiveAwaitable {
+    type Output = PyObject;
+
+    fn waiter(&mut self) -> &mut Option<PyObject> {
+        &mut self.waiter
+    }
+
+    fn poll_ready(&mut self, py: Python) -> Poll<PyObject> {
+        let mut state = self.state.borrow_mut(py);
+        if let Some(event) = state.events.pop_front() {
+            let dict = PyDict::new(py);
+            dict.set_item("type", event)?;
+            return Ok(IterNextOutput::Return(dict.into()));
+        }
+
+        let (fut, waker) = create_waiter(py)?;
+        state.receive_waiters.push(waker);
+        drop(state);
+
+        self.wait_on(py, fut)
+    }
+}
+
+#[pyproto]
+impl PyAsyncProtocol for LifespanReceiveAwaitable {
+    fn __await__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+}
+
+#[pyproto]
+impl PyIterProtocol for LifespanReceiveAwaitable {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __next__(slf: &PyCell<Self>) -> Poll<PyObject> {
+        advance(slf)
+    }
+}
+
+/// The awaitable the server waits on for the application to complete a
+/// lifespan phase.
+#[pyclass]
+pub struct PhaseAwaitable {
+    state: Py<LifespanState>,
+    task: PyObject,
+    phase: Phase,
+    mode: LifespanMode,
+
+    /// The future yielded while waiting for the application.
+    waiter: Option<PyObject>,
+}
+
+impl PhaseAwaitable {
+    /// Handles the lifespan task finishing without answering the phase.
+    ///
+    /// The application most likely does not support the lifespan protocol
+    /// and raised an error when given the scope.
+    fn task_finished(&self, py: Python) -> PyResult<()> {
+        // Retrieving the exception stops asyncio reporting it as unhandled.
+        let error = match self.task.call_method0(py, "exception") {
+            Ok(e) if !e.is_none(py) => e.as_ref(py).repr()?.to_string(),
+            Ok(_) => "the lifespan scope returned".to_string(),
+            Err(e) => e.to_string(),
+        };
+
+        if (self.mode == LifespanMode::On) | (self.phase == Phase::Shutdown) {
+            return Err(PyRuntimeError::new_err(format!(
+                "application did not complete {}: {}",
+                self.phase.event(),
+                error,
+            )));
+        }
+
+        info!(
+            "application does not support the lifespan protocol: {}",
+            error
+        );
+        self.state.borrow_mut(py).supported = false;
+
+        Ok(())
+    }
+}
+
+impl Awaitable for PhaseAwaitable {
+    type Output = ();
+
+    fn waiter(&mut self) -> &mut Option<PyObject> {
+        &mut self.waiter
+    }
+
+    fn poll_ready(&mut self, py: Python) -> Poll<()> {
+        // The reply is left in place so any further reply to the phase is
+        // rejected as a duplicate.
+        let state = self.state.borrow(py);
+        match state.reply.as_ref() {
+            Some(Reply::Complete) => {
+                info!("application {} complete", self.phase.event());
+                return Ok(IterNextOutput::Return(()));
+            },
+            Some(Reply::Failed(reason)) => {
+                error!("application {} failed: {}", self.phase.event(), reason);
+                return Err(PyRuntimeError::new_err(format!(
+                    "application {} failed: {}",
+                    self.phase.event(),
+                    reason,
+                )));
+            },
+            None => {},
+        }
+        drop(state);
+
+        let done: bool = self.task.call_method0(py, "done")?.extract(py)?;
+        if done {
+            self.task_finished(py)?;
+            return Ok(IterNextOutput::Return(()));
+        }
+
+        // Woken by either the application answering or the task finishing.
+        let (fut, waker) = create_waiter(py)?;
+        self.task
+            .call_method1(py, "add_done_callback", (waker.clone_ref(py),))?;
+        self.state.borrow_mut(py).reply_waiters.push(waker);
+
+        self.wait_on(py, fut)
+    }
+}
+
+#[pyproto]
+impl PyAsyncProtocol for PhaseAwaitable {
+    fn __await__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+}
+
+#[pyproto]
+impl PyIterProtocol for PhaseAwaitable {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __next__(slf: &PyCell<Self>) -> Poll<PyObject> {
+        advance(slf)
+    }
+}
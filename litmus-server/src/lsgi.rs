@@ -1,10 +1,11 @@
-use pyo3::types::PyBytes;
-use pyo3::Py;
+use pyo3::types::{PyBytes, PyDict, PyList, PyTuple};
+use pyo3::{Py, PyResult, Python};
 
 /// A set of headers.
 ///
-/// Each header is a (name, value) pair.
-type Headers<'a> = Vec<(&'a str, Py<PyBytes>)>;
+/// Each header is a (name, value) pair, names are lowercased when
+/// converted to Python.
+type Headers<'a> = Vec<(&'a [u8], &'a [u8])>;
 
 /// A simple tuple containing the ip string and port
 type SocketDetails = (String, u16);
@@ -12,9 +13,6 @@ type SocketDetails = (String, u16);
 /// The type of the scope call
 pub const SCOPE_TYPE: &str = "http";
 
-/// A temporary root path constant todo: allow this to be set
-pub const TEMP_ROOT_PATH: &str = "";
-
 /// The HTTP/1.0 specification
 pub const HTTP_10: &str = "1.0";
 
@@ -26,53 +24,81 @@ pub const _HTTP_2: &str = "2";
 
 /// The LSGI (Litmus Server Gateway Interface) scope that contains all state of the server and
 /// request.
-pub type LSGIScope<'a> = (
-    // type
-    //
-    // The type of scope, for a request this is "http"
-    &'static str,
-    // http_version
-    //
-    // One of "1.0", "1.1" or "2", representing HTTP/1, HTTP/1.1, HTTP/2.
-    &'static str,
-    // method
-    //
-    // The HTTP method name, in uppercase.
-    &'a str,
-    // scheme
-    //
-    // URL scheme portion, either http or https.
-    &'static str,
-    // path
-    //
-    // HTTP request target excluding any query string,
-    // with percent-encoded sequences and UTF-8 byte sequences
-    // decoded into characters.
-    &'a str,
-    // query_string
-    //
-    // URL portion after the ?, percent-encoded.
-    &'a str,
-    // root_path
-    //
-    // The root path this application is mounted at
-    &'a str,
-    // headers
-    //
-    // iterable of `(name, value)` two-item iterables, where name
-    // is the header name, and value is the header value.
-    // Order of header values must be preserved from the original
-    // HTTP request.
-    Headers<'a>,
-    // client
-    //
-    // A two-item iterable of (host, port), where host is the remote
-    // host’s IPv4 or IPv6 address, and port is the remote port
-    // as an u16.
-    SocketDetails,
-    // server
-    //
-    // A two-item iterable of (host, port), where host is the
-    // listening address for this server.
-    SocketDetails,
-);
+///
+/// This is handed to Python as a dict with ASGI compatible keys.
+pub struct LSGIScope<'a> {
+    /// One of "1.0", "1.1" or "2", representing HTTP/1, HTTP/1.1, HTTP/2.
+    pub http_version: &'static str,
+
+    /// The HTTP method name, in uppercase.
+    pub method: &'a str,
+
+    /// URL scheme portion, either http or https.
+    pub scheme: &'static str,
+
+    /// HTTP request target excluding any query string,
+    /// with percent-encoded sequences and UTF-8 byte sequences
+    /// decoded into characters.
+    pub path: &'a str,
+
+    /// The original HTTP path component unmodified from the bytes that
+    /// were received by the server.
+    pub raw_path: &'a [u8],
+
+    /// URL portion after the ?, percent-encoded.
+    pub query_string: &'a [u8],
+
+    /// The root path this application is mounted at
+    pub root_path: &'a str,
+
+    /// iterable of `(name, value)` two-item iterables, where name
+    /// is the header name, and value is the header value.
+    /// Order of header values must be preserved from the original
+    /// HTTP request.
+    pub headers: Headers<'a>,
+
+    /// A two-item iterable of (host, port), where host is the remote
+    /// host’s IPv4 or IPv6 address, and port is the remote port
+    /// as an u16.
+    pub client: SocketDetails,
+
+    /// A two-item iterable of (host, port), where host is the
+    /// listening address for this server.
+    pub server: SocketDetails,
+
+    /// The ID of the request, either given by a trusted proxy or generated
+    /// by the server.
+    pub request_id: &'a str,
+}
+
+impl<'a> LSGIScope<'a> {
+    /// Builds the Python dict representation of the scope.
+    pub fn to_dict(&self, py: Python) -> PyResult<Py<PyDict>> {
+        let headers = PyList::empty(py);
+        for (name, value) in self.headers.iter() {
+            let name = PyBytes::new_with(py, name.len(), |buff| {
+                buff.copy_from_slice(name);
+                buff.make_ascii_lowercase();
+                Ok(())
+            })?;
+            let pair = PyTuple::new(py, [name, PyBytes::new(py, value)]);
+            headers.append(pair)?;
+        }
+
+        let scope = PyDict::new(py);
+        scope.set_item("type", SCOPE_TYPE)?;
+        scope.set_item("http_version", self.http_version)?;
+        scope.set_item("method", self.method)?;
+        scope.set_item("scheme", self.scheme)?;
+        scope.set_item("path", self.path)?;
+        scope.set_item("raw_path", PyBytes::new(py, self.raw_path))?;
+        scope.set_item("query_string", PyBytes::new(py, self.query_string))?;
+        scope.set_item("root_path", self.root_path)?;
+        scope.set_item("headers", headers)?;
+        scope.set_item("client", self.client.clone())?;
+        scope.set_item("server", self.server.clone())?;
+        scope.set_item("request_id", self.request_id)?;
+
+        Ok(scope.into())
+    }
+}
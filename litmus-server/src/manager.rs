@@ -3,6 +3,8 @@ use pyo3::prelude::*;
 use slab::Slab;
 
 use crate::event_loop::{EventLoop, PreSetEventLoop};
+use crate::health::SharedHealth;
+use crate::metrics::SharedMetrics;
 use crate::net::StreamHandle;
 use crate::server::CallbackHandler;
 use crate::settings::Settings;
@@ -35,6 +37,12 @@ pub(crate) struct ClientManager<C: Reusable + PollHandler> {
 
     /// The server configuration settings.
     settings: Settings,
+
+    /// The server's metrics.
+    metrics: SharedMetrics,
+
+    /// The server's health reported by the probe endpoints.
+    health: SharedHealth,
 }
 
 impl<C: Reusable + PollHandler> ClientManager<C> {
@@ -42,31 +50,63 @@ impl<C: Reusable + PollHandler> ClientManager<C> {
         callback: CallbackHandler,
         event_loop: EventLoop,
         settings: Settings,
+        metrics: SharedMetrics,
+        health: SharedHealth,
     ) -> Self {
         Self {
             clients: Slab::with_capacity(MAX_QUEUE_SIZE),
             callback,
             event_loop,
             settings,
+            metrics,
+            health,
         }
     }
 
-    pub(crate) fn handle_connection(&mut self, conn: StreamHandle) -> PyResult<()> {
+    /// Starts handling a new connection, returning its index.
+    pub(crate) fn handle_connection(&mut self, conn: StreamHandle) -> PyResult<usize> {
         let index = self.clients.insert(None);
         debug!(
+            conn = index,
+            client:% = conn.addr;
             "creating new index {} for new connection: {:?}",
             index, conn.addr
         );
         let el = PreSetEventLoop::new(self.event_loop.clone(), conn.fd(), index);
-        let handle = C::new(self.callback.clone(), el, conn, self.settings.clone())?;
+        let handle = C::new(
+            self.callback.clone(),
+            el,
+            conn,
+            self.settings.clone(),
+            self.metrics.clone(),
+            self.health.clone(),
+        )?;
         self.clients[index].replace(handle);
 
-        Ok(())
+        self.metrics.connection_accepted();
+        self.metrics.set_active_connections(self.clients.len());
+
+        Ok(index)
+    }
+
+    /// The client at the given index if it is still being handled.
+    pub(crate) fn client(&self, index: usize) -> Option<&C> {
+        self.clients.get(index).and_then(|client| client.as_ref())
     }
 
     pub(crate) fn len_clients(&self) -> usize {
         self.clients.len()
     }
+
+    /// The tasks of the requests currently being handled by each client.
+    pub(crate) fn pending_tasks(&self, py: Python) -> Vec<PyObject> {
+        self.clients
+            .iter()
+            .filter_map(|(_, client)| client.as_ref())
+            .filter(|client| !client.is_idle())
+            .filter_map(|client| client.pending_task(py))
+            .collect()
+    }
 }
 
 impl<C: Reusable + PollHandler> RawPollHandler for ClientManager<C> {
@@ -99,12 +139,13 @@ impl<C: Reusable + PollHandler> RawPollHandler for ClientManager<C> {
     }
 
     /// Checks if any clients need to close sockets from a keep alive
-    /// timeout.
+    /// or request timeout, releasing the slots of any closed clients.
     fn poll_keep_alive(&mut self) -> PyResult<()> {
         let mut remove = Vec::new();
         for (id, client) in self.clients.iter_mut() {
             if client.is_none() {
                 remove.push(id);
+                continue;
             }
 
             let client = client.as_mut().unwrap();
@@ -119,6 +160,7 @@ impl<C: Reusable + PollHandler> RawPollHandler for ClientManager<C> {
         for id in remove {
             self.clients.remove(id);
         }
+        self.metrics.set_active_connections(self.clients.len());
 
         Ok(())
     }
@@ -0,0 +1,423 @@
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use http::StatusCode;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::health::Probes;
+
+/// The methods requests are counted by, anything else is counted as
+/// `OTHER`.
+const METHODS: [&str; 10] = [
+    "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+    "OTHER",
+];
+
+/// The classes responses are counted by.
+const STATUS_CLASSES: [&str; 5] = ["1xx", "2xx", "3xx", "4xx", "5xx"];
+
+/// The upper bounds in seconds of the request duration histogram buckets.
+const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The longest the metrics listener waits for a scrape request.
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The content type of scrapes.
+const PROMETHEUS_TYPE: &str = "text/plain; version=0.0.4";
+
+/// The content type of probe responses.
+const PLAIN_TYPE: &str = "text/plain; charset=utf-8";
+
+/// A cheaply cloneable handle to the server's metrics.
+pub(crate) type SharedMetrics = Arc<Metrics>;
+
+/// The direction data was flowing when backpressure was applied.
+#[derive(Copy, Clone)]
+pub(crate) enum Direction {
+    /// The application is not reading the request body as fast as the
+    /// client sends it.
+    Read,
+
+    /// The client is not reading the response as fast as the application
+    /// writes it.
+    Write,
+}
+
+/// The counters of the server, these are updated from the event loop and
+/// may be read from any thread.
+#[derive(Default)]
+pub struct Metrics {
+    connections_accepted: AtomicU64,
+    connections_rejected: AtomicU64,
+    connections_active: AtomicU64,
+
+    /// Requests counted by method and then status class.
+    requests: [[AtomicU64; STATUS_CLASSES.len()]; METHODS.len()],
+
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+
+    /// The number of requests in each duration bucket, the last bucket
+    /// holds any requests slower than the largest bound.
+    duration_buckets: [AtomicU64; DURATION_BUCKETS.len() + 1],
+    duration_sum_micros: AtomicU64,
+
+    parse_errors: AtomicU64,
+    keep_alive_closes: AtomicU64,
+    read_backpressure: AtomicU64,
+    write_backpressure: AtomicU64,
+    event_loop_stalls: AtomicU64,
+    requests_unanswered: AtomicU64,
+}
+
+impl Metrics {
+    pub(crate) fn connection_accepted(&self) {
+        self.connections_accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn connection_rejected(&self) {
+        self.connections_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_active_connections(&self, active: usize) {
+        self.connections_active
+            .store(active as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn bytes_received(&self, len: usize) {
+        self.bytes_received.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn bytes_sent(&self, len: usize) {
+        self.bytes_sent.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn parse_error(&self) {
+        self.parse_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn keep_alive_close(&self) {
+        self.keep_alive_closes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn event_loop_stalled(&self) {
+        self.event_loop_stalls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn backpressure(&self, direction: Direction) {
+        let counter = match direction {
+            Direction::Read => &self.read_backpressure,
+            Direction::Write => &self.write_backpressure,
+        };
+
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a completed request, a status of zero meaning the request
+    /// ended before any response was sent.
+    pub(crate) fn request_completed(&self, method: &str, status: u16, took: Duration) {
+        if status == 0 {
+            self.requests_unanswered.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        let method = METHODS
+            .iter()
+            .position(|m| *m == method)
+            .unwrap_or(METHODS.len() - 1);
+        let class = ((status / 100) as usize).clamp(1, STATUS_CLASSES.len()) - 1;
+        self.requests[method][class].fetch_add(1, Ordering::Relaxed);
+
+        let secs = took.as_secs_f64();
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(DURATION_BUCKETS.len());
+        self.duration_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.duration_sum_micros
+            .fetch_add(took.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// The cumulative count of each duration bucket followed by the total
+    /// count.
+    fn cumulative_buckets(&self) -> (Vec<u64>, u64) {
+        let mut total = 0;
+        let mut out = Vec::with_capacity(DURATION_BUCKETS.len());
+        for (index, count) in self.duration_buckets.iter().enumerate() {
+            total += count.load(Ordering::Relaxed);
+            if index < DURATION_BUCKETS.len() {
+                out.push(total);
+            }
+        }
+
+        (out, total)
+    }
+
+    fn duration_sum(&self) -> f64 {
+        self.duration_sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0
+    }
+
+    /// The plain counters as `(name, help, type, value)`.
+    fn counters(&self) -> [(&'static str, &'static str, &'static str, u64); 11] {
+        let load = |c: &AtomicU64| c.load(Ordering::Relaxed);
+
+        [
+            (
+                "connections_accepted",
+                "Connections accepted by the server.",
+                "counter",
+                load(&self.connections_accepted),
+            ),
+            (
+                "connections_rejected",
+                "Connections rejected as the server was full.",
+                "counter",
+                load(&self.connections_rejected),
+            ),
+            (
+                "connections_active",
+                "Connections currently held by the server.",
+                "gauge",
+                load(&self.connections_active),
+            ),
+            (
+                "received_bytes",
+                "Bytes read from client sockets.",
+                "counter",
+                load(&self.bytes_received),
+            ),
+            (
+                "sent_bytes",
+                "Bytes written to client sockets.",
+                "counter",
+                load(&self.bytes_sent),
+            ),
+            (
+                "parse_errors",
+                "Requests which could not be parsed.",
+                "counter",
+                load(&self.parse_errors),
+            ),
+            (
+                "keep_alive_closes",
+                "Idle connections closed by the keep alive timeout.",
+                "counter",
+                load(&self.keep_alive_closes),
+            ),
+            (
+                "read_backpressure_events",
+                "Times the application fell behind reading a request body.",
+                "counter",
+                load(&self.read_backpressure),
+            ),
+            (
+                "write_backpressure_events",
+                "Times a client fell behind reading a response.",
+                "counter",
+                load(&self.write_backpressure),
+            ),
+            (
+                "event_loop_stalls",
+                "Times the event loop was blocked for longer than the threshold.",
+                "counter",
+                load(&self.event_loop_stalls),
+            ),
+            (
+                "requests_unanswered",
+                "Requests which ended before a response was sent.",
+                "counter",
+                load(&self.requests_unanswered),
+            ),
+        ]
+    }
+
+    /// Builds a dict of every metric.
+    pub(crate) fn to_dict<'a>(&self, py: Python<'a>) -> PyResult<&'a PyDict> {
+        let out = PyDict::new(py);
+        for (name, _, _, value) in self.counters().iter() {
+            out.set_item(*name, *value)?;
+        }
+
+        let requests = PyDict::new(py);
+        for (method, counts) in METHODS.iter().zip(self.requests.iter()) {
+            let by_class = PyDict::new(py);
+            for (class, count) in STATUS_CLASSES.iter().zip(counts.iter()) {
+                by_class.set_item(*class, count.load(Ordering::Relaxed))?;
+            }
+            requests.set_item(*method, by_class)?;
+        }
+        out.set_item("requests", requests)?;
+
+        let (buckets, count) = self.cumulative_buckets();
+        let histogram = PyDict::new(py);
+        histogram.set_item(
+            "buckets",
+            DURATION_BUCKETS
+                .iter()
+                .copied()
+                .zip(buckets)
+                .collect::<Vec<_>>(),
+        )?;
+        histogram.set_item("sum", self.duration_sum())?;
+        histogram.set_item("count", count)?;
+        out.set_item("request_duration_seconds", histogram)?;
+
+        Ok(out)
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub(crate) fn to_prometheus(&self) -> String {
+        let mut out = String::with_capacity(4096);
+
+        for (name, help, kind, value) in self.counters().iter() {
+            let name = match *kind {
+                "counter" => format!("litmus_{}_total", name),
+                _ => format!("litmus_{}", name),
+            };
+
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+
+        let _ = writeln!(out, "# HELP litmus_requests_total Completed requests.");
+        let _ = writeln!(out, "# TYPE litmus_requests_total counter");
+        for (method, counts) in METHODS.iter().zip(self.requests.iter()) {
+            for (class, count) in STATUS_CLASSES.iter().zip(counts.iter()) {
+                let _ = writeln!(
+                    out,
+                    "litmus_requests_total{{method=\"{}\",status=\"{}\"}} {}",
+                    method,
+                    class,
+                    count.load(Ordering::Relaxed),
+                );
+            }
+        }
+
+        let name = "litmus_request_duration_seconds";
+        let (buckets, count) = self.cumulative_buckets();
+        let _ = writeln!(out, "# HELP {} Time taken to respond to requests.", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (bound, cumulative) in DURATION_BUCKETS.iter().zip(buckets) {
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(out, "{}_sum {}", name, self.duration_sum());
+        let _ = writeln!(out, "{}_count {}", name, count);
+
+        out
+    }
+}
+
+/// Serves the metrics in the Prometheus text format on a separate thread,
+/// along with the probe endpoints so they are answered even while the
+/// event loop is blocked.
+///
+/// The thread stops once this is dropped.
+pub(crate) struct MetricsListener {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+}
+
+impl MetricsListener {
+    /// Binds to the given address and starts serving `GET /metrics` and
+    /// the probes.
+    pub(crate) fn bind(
+        addr: &str,
+        metrics: SharedMetrics,
+        probes: Probes,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+
+        info!("serving metrics on http://{}/metrics", addr);
+
+        let stopped = stop.clone();
+        thread::Builder::new()
+            .name("litmus-metrics".to_string())
+            .spawn(move || {
+                for conn in listener.incoming() {
+                    if stopped.load(Ordering::Relaxed) {
+                        break;
+                    }
+
+                    if let Ok(conn) = conn {
+                        if let Err(e) = serve_scrape(conn, &metrics, &probes) {
+                            debug!("failed to serve metrics scrape: {}", e);
+                        }
+                    }
+                }
+            })?;
+
+        Ok(Self { addr, stop })
+    }
+}
+
+impl Drop for MetricsListener {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+
+        // Wakes the thread blocked accepting connections.
+        let _ = TcpStream::connect_timeout(&self.addr, SCRAPE_TIMEOUT);
+    }
+}
+
+/// Answers a single scrape or probe and closes the connection.
+fn serve_scrape(
+    mut conn: TcpStream,
+    metrics: &Metrics,
+    probes: &Probes,
+) -> io::Result<()> {
+    conn.set_read_timeout(Some(SCRAPE_TIMEOUT))?;
+    conn.set_write_timeout(Some(SCRAPE_TIMEOUT))?;
+
+    let mut head = Vec::with_capacity(1024);
+    let mut buffer = [0; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < 8192 {
+        let len = conn.read(&mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        head.extend_from_slice(&buffer[..len]);
+    }
+
+    let mut request_line = head.split(|&b| b == b' ');
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line
+        .next()
+        .and_then(|path| std::str::from_utf8(path).ok())
+        .unwrap_or_default();
+
+    let (status, content_type, body) = match probes.answer(path) {
+        _ if method != b"GET" => (StatusCode::NOT_FOUND, PROMETHEUS_TYPE, String::new()),
+        Some((status, body)) => (status, PLAIN_TYPE, body.to_string()),
+        None if (path == "/metrics") | (path == "/") => {
+            (StatusCode::OK, PROMETHEUS_TYPE, metrics.to_prometheus())
+        },
+        None => (StatusCode::NOT_FOUND, PROMETHEUS_TYPE, String::new()),
+    };
+
+    write!(
+        conn,
+        "HTTP/1.1 {} {}\r\n\
+         content-type: {}\r\n\
+         content-length: {}\r\n\
+         connection: close\r\n\r\n{}",
+        status.as_str(),
+        status.canonical_reason().unwrap_or(""),
+        content_type,
+        body.len(),
+        body,
+    )?;
+
+    conn.flush()
+}
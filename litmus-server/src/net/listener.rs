@@ -30,17 +30,21 @@ pub struct NoneBlockingListener {
     listener: TcpListener,
 
     pub addr: SocketAddr,
+
+    /// If connections on this listener start with a PROXY protocol header.
+    pub proxy_protocol: bool,
 }
 
 impl NoneBlockingListener {
     /// Attempts to bind to a given addresses and returns `Self`
-    pub fn bind(addr: &str) -> PyResult<Self> {
+    pub fn bind(addr: &str, proxy_protocol: bool) -> PyResult<Self> {
         let listener = TcpListener::bind(addr)?;
         listener.set_nonblocking(true).expect("set non-blocking");
 
         Ok(Self {
             listener,
             addr: addr.parse().expect("invalid addr"),
+            proxy_protocol,
         })
     }
 
@@ -58,7 +62,7 @@ impl NoneBlockingListener {
 
         stream.set_nonblocking(true).expect("set non-blocking");
 
-        let handle = StreamHandle::new(stream, addr, self.addr);
+        let handle = StreamHandle::new(stream, addr, self.addr, self.proxy_protocol);
         Ok(Status::Successful(handle))
     }
 
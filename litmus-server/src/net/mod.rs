@@ -1,5 +1,7 @@
 mod listener;
+mod proxy;
 mod stream;
 
 pub use listener::{NoneBlockingListener, Status};
+pub use proxy::{parse_proxy_header, ProxyStatus};
 pub use stream::{SocketStatus, StreamHandle};
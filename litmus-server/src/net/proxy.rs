@@ -0,0 +1,363 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str;
+
+/// The signature every PROXY protocol v1 header starts with.
+const V1_PREFIX: &[u8] = b"PROXY ";
+
+/// The max length of a PROXY protocol v1 header including the CRLF.
+const V1_MAX_LENGTH: usize = 107;
+
+/// The signature every PROXY protocol v2 header starts with.
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\x00\r\nQUIT\n";
+
+/// The length of the fixed part of a PROXY protocol v2 header.
+const V2_HEADER_LENGTH: usize = 16;
+
+/// The result of attempting to parse a PROXY protocol header.
+pub enum ProxyStatus {
+    /// More data is needed before the header can be parsed.
+    Partial,
+
+    /// The data is not a valid PROXY protocol header.
+    Invalid,
+
+    /// The header was parsed.
+    ///
+    /// `len` is the length of the header in bytes and `addrs` is the
+    /// advertised (source, destination) pair, this is `None` when the
+    /// proxy does not give the original addresses e.g. for health checks.
+    Complete {
+        len: usize,
+        addrs: Option<(SocketAddr, SocketAddr)>,
+    },
+}
+
+/// Parses a HAProxy PROXY protocol v1 or v2 header from the start of the
+/// given buffer.
+pub fn parse_proxy_header(buffer: &[u8]) -> ProxyStatus {
+    if buffer.len() < V1_PREFIX.len() {
+        return if V1_PREFIX.starts_with(buffer) | V2_SIGNATURE.starts_with(buffer) {
+            ProxyStatus::Partial
+        } else {
+            ProxyStatus::Invalid
+        };
+    }
+
+    if buffer.starts_with(V1_PREFIX) {
+        parse_v1(buffer)
+    } else if V2_SIGNATURE.starts_with(&buffer[..buffer.len().min(V2_SIGNATURE.len())]) {
+        parse_v2(buffer)
+    } else {
+        ProxyStatus::Invalid
+    }
+}
+
+/// Parses the human readable v1 header e.g.
+/// `PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n`
+fn parse_v1(buffer: &[u8]) -> ProxyStatus {
+    let end = match buffer.windows(2).position(|w| w == b"\r\n") {
+        Some(end) => end,
+        None if buffer.len() < V1_MAX_LENGTH => return ProxyStatus::Partial,
+        None => return ProxyStatus::Invalid,
+    };
+
+    let line = match str::from_utf8(&buffer[..end]) {
+        Ok(line) => line,
+        Err(_) => return ProxyStatus::Invalid,
+    };
+
+    let len = end + 2;
+    let parts: Vec<&str> = line.split(' ').collect();
+    match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => ProxyStatus::Complete { len, addrs: None },
+        ["PROXY", family, src, dst, src_port, dst_port] => {
+            let addrs = parse_v1_addrs(family, src, dst, src_port, dst_port);
+            match addrs {
+                Some(addrs) => ProxyStatus::Complete {
+                    len,
+                    addrs: Some(addrs),
+                },
+                None => ProxyStatus::Invalid,
+            }
+        },
+        _ => ProxyStatus::Invalid,
+    }
+}
+
+/// Parses the addresses of a v1 header.
+fn parse_v1_addrs(
+    family: &str,
+    src: &str,
+    dst: &str,
+    src_port: &str,
+    dst_port: &str,
+) -> Option<(SocketAddr, SocketAddr)> {
+    let (src, dst): (IpAddr, IpAddr) = match family {
+        "TCP4" => (
+            src.parse::<Ipv4Addr>().ok()?.into(),
+            dst.parse::<Ipv4Addr>().ok()?.into(),
+        ),
+        "TCP6" => (
+            src.parse::<Ipv6Addr>().ok()?.into(),
+            dst.parse::<Ipv6Addr>().ok()?.into(),
+        ),
+        _ => return None,
+    };
+
+    Some((
+        SocketAddr::new(src, src_port.parse().ok()?),
+        SocketAddr::new(dst, dst_port.parse().ok()?),
+    ))
+}
+
+/// Parses the binary v2 header.
+fn parse_v2(buffer: &[u8]) -> ProxyStatus {
+    if buffer.len() < V2_HEADER_LENGTH {
+        return ProxyStatus::Partial;
+    }
+
+    let version_command = buffer[12];
+    let family = buffer[13];
+    let addrs_len = u16::from_be_bytes([buffer[14], buffer[15]]) as usize;
+    let len = V2_HEADER_LENGTH + addrs_len;
+
+    if version_command >> 4 != 2 {
+        return ProxyStatus::Invalid;
+    }
+
+    if buffer.len() < len {
+        return ProxyStatus::Partial;
+    }
+
+    // The LOCAL command is used by the proxy itself e.g. for health checks.
+    if version_command & 0x0F == 0 {
+        return ProxyStatus::Complete { len, addrs: None };
+    }
+
+    if version_command & 0x0F != 1 {
+        return ProxyStatus::Invalid;
+    }
+
+    let addrs = &buffer[V2_HEADER_LENGTH..len];
+    let addrs = match family >> 4 {
+        // AF_INET
+        1 if addrs.len() >= 12 => {
+            let src = Ipv4Addr::new(addrs[0], addrs[1], addrs[2], addrs[3]);
+            let dst = Ipv4Addr::new(addrs[4], addrs[5], addrs[6], addrs[7]);
+            Some((
+                SocketAddr::new(src.into(), u16::from_be_bytes([addrs[8], addrs[9]])),
+                SocketAddr::new(dst.into(), u16::from_be_bytes([addrs[10], addrs[11]])),
+            ))
+        },
+        // AF_INET6
+        2 if addrs.len() >= 36 => {
+            let mut src = [0u8; 16];
+            let mut dst = [0u8; 16];
+            src.copy_from_slice(&addrs[..16]);
+            dst.copy_from_slice(&addrs[16..32]);
+            Some((
+                SocketAddr::new(
+                    Ipv6Addr::from(src).into(),
+                    u16::from_be_bytes([addrs[32], addrs[33]]),
+                ),
+                SocketAddr::new(
+                    Ipv6Addr::from(dst).into(),
+                    u16::from_be_bytes([addrs[34], addrs[35]]),
+                ),
+            ))
+        },
+        // AF_UNSPEC and AF_UNIX have no addresses we can use.
+        0 | 3 => None,
+        _ => return ProxyStatus::Invalid,
+    };
+
+    ProxyStatus::Complete { len, addrs }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs(src: &str, dst: &str) -> Option<(SocketAddr, SocketAddr)> {
+        Some((src.parse().unwrap(), dst.parse().unwrap()))
+    }
+
+    /// Builds a v2 header with the given command, family and address block.
+    fn v2(version_command: u8, family: u8, block: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(version_command);
+        header.push(family);
+        header.extend_from_slice(&(block.len() as u16).to_be_bytes());
+        header.extend_from_slice(block);
+        header
+    }
+
+    #[test]
+    fn v1_tcp4() {
+        let header = b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\nGET /";
+        match parse_proxy_header(header) {
+            ProxyStatus::Complete { len, addrs: parsed } => {
+                assert_eq!(len, header.len() - 5);
+                assert_eq!(parsed, addrs("192.168.0.1:56324", "192.168.0.11:443"));
+            },
+            _ => panic!("expected a complete header"),
+        }
+    }
+
+    #[test]
+    fn v1_tcp6() {
+        let header = b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 80\r\n";
+        match parse_proxy_header(header) {
+            ProxyStatus::Complete { len, addrs: parsed } => {
+                assert_eq!(len, header.len());
+                assert_eq!(parsed, addrs("[2001:db8::1]:4000", "[2001:db8::2]:80"));
+            },
+            _ => panic!("expected a complete header"),
+        }
+    }
+
+    #[test]
+    fn v1_unknown_has_no_addresses() {
+        let header = b"PROXY UNKNOWN ffff::1 ffff::2 1 2\r\n";
+        assert!(matches!(
+            parse_proxy_header(header),
+            ProxyStatus::Complete { len, addrs: None } if len == header.len()
+        ));
+    }
+
+    #[test]
+    fn v1_partial() {
+        assert!(matches!(parse_proxy_header(b""), ProxyStatus::Partial));
+        assert!(matches!(parse_proxy_header(b"PRO"), ProxyStatus::Partial));
+        assert!(matches!(
+            parse_proxy_header(b"PROXY TCP4 192.168.0.1 "),
+            ProxyStatus::Partial
+        ));
+    }
+
+    #[test]
+    fn v1_invalid() {
+        assert!(matches!(
+            parse_proxy_header(b"GET / HTTP/1.1\r\n"),
+            ProxyStatus::Invalid
+        ));
+        assert!(matches!(
+            parse_proxy_header(b"PROXY TCP4 192.168.0.1 2001:db8::2 1 2\r\n"),
+            ProxyStatus::Invalid
+        ));
+        assert!(matches!(
+            parse_proxy_header(b"PROXY TCP4 192.168.0.1 192.168.0.2 1 99999\r\n"),
+            ProxyStatus::Invalid
+        ));
+        assert!(matches!(
+            parse_proxy_header(b"PROXY UDP4 192.168.0.1 192.168.0.2 1 2\r\n"),
+            ProxyStatus::Invalid
+        ));
+        assert!(matches!(
+            parse_proxy_header(b"PROXY TCP4 192.168.0.1\r\n"),
+            ProxyStatus::Invalid
+        ));
+
+        let mut too_long = b"PROXY ".to_vec();
+        too_long.resize(V1_MAX_LENGTH, b'1');
+        assert!(matches!(
+            parse_proxy_header(&too_long),
+            ProxyStatus::Invalid
+        ));
+    }
+
+    #[test]
+    fn v2_ipv4() {
+        let mut header = v2(
+            0x21,
+            0x11,
+            &[192, 168, 0, 1, 10, 0, 0, 1, 0xDC, 0x04, 0x01, 0xBB],
+        );
+        let len = header.len();
+        header.extend_from_slice(b"GET /");
+
+        match parse_proxy_header(&header) {
+            ProxyStatus::Complete {
+                len: parsed_len,
+                addrs: parsed,
+            } => {
+                assert_eq!(parsed_len, len);
+                assert_eq!(parsed, addrs("192.168.0.1:56324", "10.0.0.1:443"));
+            },
+            _ => panic!("expected a complete header"),
+        }
+    }
+
+    #[test]
+    fn v2_ipv6_with_trailing_tlvs() {
+        let mut block = Vec::new();
+        block.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        block.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        block.extend_from_slice(&4000u16.to_be_bytes());
+        block.extend_from_slice(&80u16.to_be_bytes());
+        // A TLV the parser does not use.
+        block.extend_from_slice(&[0x04, 0x00, 0x01, 0xFF]);
+        let header = v2(0x21, 0x21, &block);
+
+        match parse_proxy_header(&header) {
+            ProxyStatus::Complete { len, addrs: parsed } => {
+                assert_eq!(len, header.len());
+                assert_eq!(parsed, addrs("[2001:db8::1]:4000", "[2001:db8::2]:80"));
+            },
+            _ => panic!("expected a complete header"),
+        }
+    }
+
+    #[test]
+    fn v2_local_and_unspecified_have_no_addresses() {
+        let header = v2(0x20, 0x00, &[]);
+        assert!(matches!(
+            parse_proxy_header(&header),
+            ProxyStatus::Complete {
+                len: 16,
+                addrs: None
+            }
+        ));
+
+        let header = v2(0x21, 0x00, &[]);
+        assert!(matches!(
+            parse_proxy_header(&header),
+            ProxyStatus::Complete {
+                len: 16,
+                addrs: None
+            }
+        ));
+    }
+
+    #[test]
+    fn v2_partial() {
+        let header = v2(0x21, 0x11, &[192, 168, 0, 1, 10, 0, 0, 1, 0, 1, 0, 2]);
+        for end in [1, 8, 12, 15, 16, header.len() - 1] {
+            assert!(
+                matches!(parse_proxy_header(&header[..end]), ProxyStatus::Partial),
+                "{} bytes should be partial",
+                end,
+            );
+        }
+    }
+
+    #[test]
+    fn v2_invalid() {
+        // Version 1 in the binary format.
+        let header = v2(0x11, 0x11, &[0; 12]);
+        assert!(matches!(parse_proxy_header(&header), ProxyStatus::Invalid));
+
+        // An unknown command.
+        let header = v2(0x22, 0x11, &[0; 12]);
+        assert!(matches!(parse_proxy_header(&header), ProxyStatus::Invalid));
+
+        // An unknown address family.
+        let header = v2(0x21, 0x41, &[0; 12]);
+        assert!(matches!(parse_proxy_header(&header), ProxyStatus::Invalid));
+
+        // A broken signature.
+        let mut header = v2(0x21, 0x11, &[0; 12]);
+        header[4] = b'x';
+        assert!(matches!(parse_proxy_header(&header), ProxyStatus::Invalid));
+    }
+}
@@ -7,6 +7,10 @@ use std::os::windows::io::AsRawSocket;
 
 use bytes::{BufMut, BytesMut};
 use pyo3::{PyErr, PyResult};
+use tracing::instrument;
+
+use crate::event_loop::{SocketFd, NO_SOCKET};
+use crate::testing::MemorySocket;
 
 pub enum SocketStatus {
     Complete(usize),
@@ -14,12 +18,20 @@ pub enum SocketStatus {
     Disconnect,
 }
 
+/// The socket a handle reads from and writes to.
+enum Stream {
+    /// The internal `std::net::TcpStream` instance that should be set
+    /// to be non-blocking.
+    Tcp(TcpStream),
+
+    /// An in-memory connection driven by the test client.
+    Memory(MemorySocket),
+}
+
 /// A struct that wraps a given TcpStream and SocketAddr and produces a
 /// contain for interactions that are os agnostic.
 pub struct StreamHandle {
-    /// The internal `std::net::TcpStream` instance that should be set
-    /// to be non-blocking.
-    stream: TcpStream,
+    stream: Stream,
 
     /// The remote's given socket addr as given by the tcp listener upon
     /// accepting the client / connection.
@@ -28,40 +40,87 @@ pub struct StreamHandle {
     pub server: SocketAddr,
 
     pub tls: bool,
+
+    /// If the stream starts with a PROXY protocol header.
+    pub proxy_protocol: bool,
 }
 
 impl StreamHandle {
     /// Create a new tcp handle wrapping the given stream and addr.
-    pub fn new(stream: TcpStream, addr: SocketAddr, server: SocketAddr) -> Self {
+    pub fn new(
+        stream: TcpStream,
+        addr: SocketAddr,
+        server: SocketAddr,
+        proxy_protocol: bool,
+    ) -> Self {
         Self {
-            stream,
+            stream: Stream::Tcp(stream),
             addr,
             server,
             tls: false,
+            proxy_protocol,
+        }
+    }
+
+    /// Create a new handle over an in-memory connection from `addr` to
+    /// `server`.
+    pub(crate) fn in_memory(
+        socket: MemorySocket,
+        addr: SocketAddr,
+        server: SocketAddr,
+        tls: bool,
+    ) -> Self {
+        Self {
+            stream: Stream::Memory(socket),
+            addr,
+            server,
+            tls,
+            proxy_protocol: false,
+        }
+    }
+
+    /// The in-memory socket if the connection is not over TCP.
+    pub(crate) fn memory(&self) -> Option<&MemorySocket> {
+        match &self.stream {
+            Stream::Tcp(_) => None,
+            Stream::Memory(socket) => Some(socket),
         }
     }
 
-    /// Returns the raw file descriptor of the socket.
+    /// Returns the raw file descriptor of the socket, in-memory connections
+    /// have none and are never registered with the event loop.
     #[cfg(windows)]
-    pub fn fd(&self) -> u64 {
-        self.stream.as_raw_socket()
+    pub fn fd(&self) -> SocketFd {
+        match &self.stream {
+            Stream::Tcp(stream) => stream.as_raw_socket(),
+            Stream::Memory(_) => NO_SOCKET,
+        }
     }
 
-    /// Returns the raw file descriptor of the socket.
+    /// Returns the raw file descriptor of the socket, in-memory connections
+    /// have none and are never registered with the event loop.
     #[cfg(unix)]
-    pub fn fd(&self) -> i32 {
-        self.stream.as_raw_fd()
+    pub fn fd(&self) -> SocketFd {
+        match &self.stream {
+            Stream::Tcp(stream) => stream.as_raw_fd(),
+            Stream::Memory(_) => NO_SOCKET,
+        }
     }
 
     /// Reads the data from the socket to the supplied buffer returning
     /// a result with the number of bytes read if the operation is a success.
-    #[timed::timed(duration(printer = "trace!"))]
+    #[instrument(level = "trace", skip_all)]
     pub fn read(&mut self, buffer: &mut BytesMut) -> PyResult<SocketStatus> {
+        let stream = match &mut self.stream {
+            Stream::Tcp(stream) => stream,
+            Stream::Memory(socket) => return Ok(socket.read(buffer)),
+        };
+
         let data = buffer.chunk_mut();
-        let mut slice =
+        let slice =
             unsafe { std::slice::from_raw_parts_mut(data.as_mut_ptr(), data.len()) };
 
-        let len = match self.stream.read(&mut slice) {
+        let len = match stream.read(slice) {
             Ok(n) => n,
             Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
                 return Ok(SocketStatus::WouldBlock)
@@ -85,13 +144,21 @@ impl StreamHandle {
     /// Writes the data from the supplied buffer to the socket returning a
     /// result with the number of bytes written to the socket if the operation
     /// is a success.
-    #[timed::timed(duration(printer = "trace!"))]
+    #[instrument(level = "trace", skip_all)]
     pub fn write(&mut self, buffer: &mut BytesMut) -> PyResult<SocketStatus> {
-        let len = match self.stream.write(buffer) {
+        let stream = match &mut self.stream {
+            Stream::Tcp(stream) => stream,
+            Stream::Memory(socket) => return Ok(socket.write(buffer)),
+        };
+
+        let len = match stream.write(buffer) {
             Ok(n) => n,
             Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
                 return Ok(SocketStatus::WouldBlock)
             },
+            Err(ref e) if e.kind() == ErrorKind::BrokenPipe => {
+                return Ok(SocketStatus::Disconnect)
+            },
             Err(ref e) if e.kind() == ErrorKind::ConnectionReset => {
                 return Ok(SocketStatus::Disconnect)
             },
@@ -107,6 +174,11 @@ impl StreamHandle {
     }
 
     pub fn close(&mut self) {
-        let _ = self.stream.shutdown(Shutdown::Both);
+        match &self.stream {
+            Stream::Tcp(stream) => {
+                let _ = stream.shutdown(Shutdown::Both);
+            },
+            Stream::Memory(socket) => socket.shutdown(),
+        }
     }
 }
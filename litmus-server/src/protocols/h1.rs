@@ -1,28 +1,36 @@
-#![allow(deprecated)]
-
-use std::{mem, str};
+use std::str;
+use std::time::{Duration, Instant};
 
 use bytes::BytesMut;
-use http::header::{CONTENT_LENGTH, TRANSFER_ENCODING};
-use http::uri::Uri;
-use httparse::{parse_chunk_size, Header, Request, Status};
+use http::header::{CONNECTION, CONTENT_LENGTH, TRANSFER_ENCODING};
+use http::StatusCode;
+use httparse::{parse_chunk_size, Header, Request, Status, EMPTY_HEADER};
+use log::Level;
 use pyo3::exceptions::PyRuntimeError;
-use pyo3::types::PyBytes;
-use pyo3::{Py, PyResult, Python};
+use pyo3::{PyObject, PyResult, Python};
+use tracing::{field, info_span, Span};
 
-use crate::protocols::selector::SwitchStatus;
+use crate::access_log::{response_status, AccessRecord, ACCESS_LOG_TARGET};
+use crate::forwarded::{self, ForwardedHeaders};
+use crate::health::{InFlight, SharedHealth};
 use crate::lsgi;
+use crate::metrics::{Direction, SharedMetrics};
+use crate::protocols::selector::{ConnectionState, SwitchStatus};
+use crate::protocols::target::RequestTarget;
 use crate::responders::{ReceiverFactory, SenderFactory};
 use crate::server::CallbackHandler;
 use crate::settings::Settings;
 use crate::traits::{BaseTransport, ProtocolBuffers};
 use crate::transport::Transport;
 
-macro_rules! conv_err {
-    ( $e:expr ) => {
-        $e.map_err(|e| PyRuntimeError::new_err(format!("{}", e)))
-    };
-}
+/// The header a proxy uses to tell the server the prefix it is mounted at.
+const X_FORWARDED_PREFIX: &str = "x-forwarded-prefix";
+
+/// The header a proxy uses to pass on the ID it gave the request.
+const X_REQUEST_ID: &str = "x-request-id";
+
+/// The longest request ID accepted from a proxy.
+const MAX_REQUEST_ID_LEN: usize = 128;
 
 /// The max headers allowed in a single request.
 const MAX_HEADERS: usize = 100;
@@ -34,6 +42,58 @@ const MIN_BUFF_SIZE: usize = 64 * 1024;
 /// if they go above the MIN_BUFF_SIZE
 const FORGIVING_BUFFER_SIZE: usize = 128 * 1024;
 
+/// Builds a complete response that is produced by the server itself rather
+/// than the application, these responses close the connection unless
+/// `keep_alive` is set.
+///
+/// A non-empty body is sent as plain text.
+pub(crate) fn server_response(
+    status: StatusCode,
+    body: &str,
+    keep_alive: bool,
+) -> Vec<u8> {
+    let content_type = if body.is_empty() {
+        ""
+    } else {
+        "content-type: text/plain; charset=utf-8\r\n"
+    };
+
+    let connection = if keep_alive {
+        ""
+    } else {
+        "connection: close\r\n"
+    };
+
+    format!(
+        "HTTP/1.1 {} {}\r\n\
+         content-length: {}\r\n\
+         {}\
+         {}\
+         date: {}\r\n\
+         server: Pyre\r\n\r\n{}",
+        status.as_str(),
+        status.canonical_reason().unwrap_or(""),
+        body.len(),
+        content_type,
+        connection,
+        httpdate::fmt_http_date(std::time::SystemTime::now()),
+        body,
+    )
+    .into_bytes()
+}
+
+/// What became of a request once its head was parsed.
+enum Dispatch {
+    /// The request was handed to the application.
+    Application,
+
+    /// The request target was invalid so the request was rejected.
+    InvalidTarget,
+
+    /// The request was answered by the server without the application.
+    Answered,
+}
+
 /// The protocol to add handling for the HTTP/1.x protocol.
 pub struct H1Protocol {
     /// A possible Transport struct, this can be None if the protocol
@@ -42,7 +102,6 @@ pub struct H1Protocol {
     maybe_transport: Option<Transport>,
 
     /// The server configuration used to construct a ASGI scope.
-    #[allow(unused)]
     settings: Settings,
 
     /// The python callback handler.
@@ -64,11 +123,52 @@ pub struct H1Protocol {
     /// If the server should close the connection after the response is
     /// complete.
     keep_alive: bool,
+
+    /// The stage of the request / response cycle the connection is at.
+    state: ConnectionState,
+
+    /// The number of requests that have been received on this connection.
+    requests_handled: usize,
+
+    /// A response generated by the server that takes priority over
+    /// anything sent by the application.
+    server_response: Option<Vec<u8>>,
+
+    /// The task returned by the callback for the current request.
+    task: Option<PyObject>,
+
+    /// The access log record of the current request if access logging
+    /// is enabled.
+    access: Option<AccessRecord>,
+
+    /// The server's metrics.
+    metrics: SharedMetrics,
+
+    /// The method of the current request and when it was received.
+    request_started: Option<(String, Instant)>,
+
+    /// The status of the current response once it has started.
+    response_status: Option<u16>,
+
+    /// The span covering the current request, closed once the response
+    /// completes.
+    request_span: Option<Span>,
+
+    /// The server's health reported by the probe endpoints.
+    health: SharedHealth,
+
+    /// The key of the current request in the health's in flight requests.
+    in_flight: Option<usize>,
 }
 
 impl H1Protocol {
     /// Create a new H1Protocol instance.
-    pub(crate) fn new(settings: Settings, callback: CallbackHandler) -> Self {
+    pub(crate) fn new(
+        settings: Settings,
+        callback: CallbackHandler,
+        metrics: SharedMetrics,
+        health: SharedHealth,
+    ) -> Self {
         let sender = SenderFactory::new();
         let receiver = ReceiverFactory::new();
 
@@ -83,6 +183,17 @@ impl H1Protocol {
             expected_content_length: 0,
             chunked_encoding: false,
             keep_alive: true,
+            state: ConnectionState::Idle,
+            requests_handled: 0,
+            server_response: None,
+            task: None,
+            access: None,
+            metrics,
+            request_started: None,
+            response_status: None,
+            request_span: None,
+            health,
+            in_flight: None,
         }
     }
 
@@ -94,13 +205,13 @@ impl H1Protocol {
     /// confusing.
     #[inline]
     fn transport(&self) -> PyResult<&Transport> {
-        return if let Some(t) = self.maybe_transport.as_ref() {
+        if let Some(t) = self.maybe_transport.as_ref() {
             Ok(t)
         } else {
             Err(PyRuntimeError::new_err(
                 "transport was None upon being called",
             ))
-        };
+        }
     }
 }
 
@@ -115,7 +226,33 @@ impl H1Protocol {
 
     /// Called when the connection is lost from the protocol in order to
     /// properly reset state.
+    ///
+    /// Any responders of the current request are told the client has
+    /// disconnected, waking anything waiting on them. If enabled the task
+    /// handling the request is cancelled once the grace period is over.
     pub fn lost_connection(&mut self) -> PyResult<()> {
+        self.sender.disconnect();
+        self.receiver.disconnect();
+
+        let in_flight = self.state != ConnectionState::Idle;
+        self.request_span = None;
+        self.finish_in_flight();
+
+        // The request was cut short, it is counted as unanswered if the
+        // client never saw the start of a response.
+        if let Some((method, started)) = self.request_started.take() {
+            let status = self.response_status.take().unwrap_or(0);
+            self.metrics
+                .request_completed(&method, status, started.elapsed());
+        }
+        if let Some(task) = self.task.take() {
+            if self.settings.cancel_on_disconnect & in_flight {
+                Python::with_gil(|py| {
+                    let _ = cancel_task(py, task, self.settings.cancel_grace_period);
+                });
+            }
+        }
+
         Ok(())
     }
 
@@ -124,6 +261,15 @@ impl H1Protocol {
     fn reset_state(&mut self) {
         self.expected_content_length = 0;
         self.chunked_encoding = false;
+        self.state = ConnectionState::Idle;
+        self.requests_handled = 0;
+        self.server_response = None;
+        self.task = None;
+        self.access = None;
+        self.request_started = None;
+        self.response_status = None;
+        self.request_span = None;
+        self.finish_in_flight();
 
         self.sender = SenderFactory::new();
         self.receiver = ReceiverFactory::new();
@@ -136,6 +282,152 @@ impl H1Protocol {
         // ignore for now
         Ok(SwitchStatus::NoSwitch)
     }
+
+    /// The task handling the current request if it is still running.
+    pub(crate) fn pending_task(&self, py: Python) -> Option<PyObject> {
+        let task = self.task.as_ref()?;
+        let done = task
+            .call_method0(py, "done")
+            .and_then(|done| done.extract::<bool>(py))
+            .unwrap_or(true);
+
+        if done {
+            None
+        } else {
+            Some(task.clone_ref(py))
+        }
+    }
+
+    /// The current stage of the request / response cycle.
+    pub(crate) fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    /// Abandons the current request, responding with a
+    /// `408 Request Timeout` and closing the connection once it is written.
+    pub(crate) fn request_timed_out(&mut self) -> PyResult<()> {
+        self.send_server_response(StatusCode::REQUEST_TIMEOUT, "")
+    }
+
+    /// If the application's task has finished without completing the
+    /// response, leaving nothing queued to be written.
+    pub(crate) fn response_abandoned(&self, py: Python) -> bool {
+        (self.state == ConnectionState::Responding)
+            & self.task.is_some()
+            & self.pending_task(py).is_none()
+            & self.sender.is_empty()
+    }
+
+    /// Gives up on the response to the current request, cancelling the
+    /// application's task if it is still running.
+    ///
+    /// A `500 Internal Server Error` is sent in its place if nothing has
+    /// been written yet, otherwise the connection is closed as the client
+    /// cannot be sent the rest of it.
+    pub(crate) fn abandon_response(&mut self) -> PyResult<()> {
+        if let Some(task) = self.task.take() {
+            Python::with_gil(|py| cancel_task(py, task, Duration::ZERO))?;
+        }
+
+        if self.response_status.is_none() {
+            return self.send_server_response(StatusCode::INTERNAL_SERVER_ERROR, "");
+        }
+
+        self.keep_alive = false;
+        self.state = ConnectionState::Idle;
+        self.request_completed();
+        self.transport()?.close()
+    }
+
+    /// Records the metrics and emits the access log record of the request
+    /// that just completed.
+    fn request_completed(&mut self) {
+        self.finish_in_flight();
+
+        if let Some((method, started)) = self.request_started.take() {
+            let status = self.response_status.take().unwrap_or(0);
+            self.metrics
+                .request_completed(&method, status, started.elapsed());
+
+            if let Some(span) = self.request_span.take() {
+                span.record("status", status);
+            }
+        }
+
+        if let Some(record) = self.access.take() {
+            info!(
+                target: ACCESS_LOG_TARGET,
+                "{}",
+                self.settings.access_log_format.format(&record)
+            );
+        }
+    }
+
+    /// Removes the current request from the health's in flight requests.
+    fn finish_in_flight(&mut self) {
+        if let Some(key) = self.in_flight.take() {
+            self.health.request_finished(key);
+        }
+    }
+
+    /// Queues a response generated by the server to be written in place
+    /// of anything the application sends, the connection is closed after.
+    fn send_server_response(&mut self, status: StatusCode, body: &str) -> PyResult<()> {
+        self.keep_alive = false;
+        self.server_response = Some(server_response(status, body, false));
+        self.transport()?.resume_writing()
+    }
+
+    /// Answers the request if it is for one of the health or readiness
+    /// endpoints, returning true if it was answered.
+    ///
+    /// The connection is kept open for the next probe unless the request
+    /// has a body, which is never read, or the client asked to close it.
+    fn answer_probe(
+        &mut self,
+        method: &str,
+        path: &str,
+        headers: &[Header],
+    ) -> PyResult<bool> {
+        if (method != "GET") & (method != "HEAD") {
+            return Ok(false);
+        }
+
+        let is_path = |probe: &Option<String>| probe.as_deref() == Some(path);
+        let (status, body) = if is_path(&self.settings.health_path) {
+            self.health.liveness(self.settings.heartbeat_timeout)
+        } else if is_path(&self.settings.ready_path) {
+            self.health.readiness()
+        } else {
+            return Ok(false);
+        };
+
+        for header in headers {
+            let has_body = ((header.name == CONTENT_LENGTH) & (header.value != b"0"))
+                | (header.name == TRANSFER_ENCODING);
+            let wants_close = (header.name == CONNECTION)
+                & header
+                    .value
+                    .to_ascii_lowercase()
+                    .windows(5)
+                    .any(|w| w == b"close");
+
+            if has_body | wants_close {
+                self.keep_alive = false;
+            }
+        }
+
+        let mut response = server_response(status, body, self.keep_alive);
+        if method == "HEAD" {
+            // The length of the body is still advertised.
+            response.truncate(response.len() - body.len());
+        }
+
+        self.server_response = Some(response);
+        self.transport()?.resume_writing()?;
+
+        Ok(true)
+    }
 }
 
 impl ProtocolBuffers for H1Protocol {
@@ -150,17 +442,12 @@ impl ProtocolBuffers for H1Protocol {
     /// Upon no data being read signalling a EOF the eof_received callback is
     /// invoked and handled instead.
     fn data_received(&mut self, buffer: &mut BytesMut) -> PyResult<()> {
-        if self.expected_content_length == 0 {
+        if let ConnectionState::Idle | ConnectionState::ReadingHead = self.state {
+            self.state = ConnectionState::ReadingHead;
             self.parser_request(buffer)?;
         }
 
-        if self.chunked_encoding {
-            self.parse_chunked_body(buffer)?;
-        } else if self.expected_content_length > 0 {
-            self.parse_body(buffer)?;
-        } else {
-            let _ = self.receiver.send((false, BytesMut::with_capacity(0)));
-        }
+        self.read_body(buffer)?;
 
         self.transport()?.resume_writing()?;
         Ok(())
@@ -168,10 +455,43 @@ impl ProtocolBuffers for H1Protocol {
 
     /// Fills the passed buffer with any messages enqueued to be sent.
     fn fill_write_buffer(&mut self, buffer: &mut BytesMut) -> PyResult<()> {
+        if let Some(response) = self.server_response.take() {
+            if let Some(record) = self.access.as_mut() {
+                record.on_write(&response);
+            }
+            self.response_status = response_status(&response);
+            self.request_completed();
+
+            buffer.extend(response);
+            self.state = ConnectionState::Idle;
+            if !self.keep_alive {
+                self.transport()?.close()?;
+            }
+
+            return Ok(());
+        }
+
         while let Ok((more_body, keep_alive, buff)) = self.sender.recv() {
-            self.keep_alive = keep_alive;
+            self.keep_alive &= keep_alive;
+            if let Some(record) = self.access.as_mut() {
+                record.on_write(&buff);
+            }
+            if self.response_status.is_none() {
+                self.response_status = response_status(&buff);
+            }
             buffer.extend(buff);
 
+            if !more_body {
+                // The rest of a body the application stopped reading is
+                // still on the socket so the connection cannot be reused.
+                if self.receiver.is_read_paused() {
+                    self.keep_alive = false;
+                }
+
+                self.state = ConnectionState::Idle;
+                self.request_completed();
+            }
+
             if !more_body & !self.keep_alive {
                 // This will schedule the closure using call_soon.
                 self.transport()?.close()?;
@@ -184,60 +504,172 @@ impl ProtocolBuffers for H1Protocol {
 
 impl H1Protocol {
     fn parser_request(&mut self, buffer: &mut BytesMut) -> PyResult<()> {
-        // This should be fine as it is guaranteed to be initialised
-        // before we use it, just waiting for the ability to use
-        // MaybeUninit, till then here we are.
-        let mut headers: [Header<'_>; MAX_HEADERS] = unsafe { mem::uninitialized() };
+        let mut headers = [EMPTY_HEADER; MAX_HEADERS];
 
         let body = buffer.clone();
 
         let mut request = Request::new(&mut headers);
-        let status = conv_err!(request.parse(&body))?;
-
-        let len = if status.is_partial() {
-            return Ok(());
-        } else {
-            status.unwrap()
+        let len = match request.parse(&body) {
+            Ok(Status::Complete(len)) => len,
+            Ok(Status::Partial) => return Ok(()),
+            Err(e) => {
+                debug!(
+                    client:% = self.transport()?.client;
+                    "rejecting malformed request, {}",
+                    e
+                );
+
+                // Nothing after the malformed head can be trusted.
+                buffer.clear();
+                self.metrics.parse_error();
+                self.state = ConnectionState::Responding;
+                return self.send_server_response(StatusCode::BAD_REQUEST, "");
+            },
         };
 
         let _ = buffer.split_to(len);
 
-        self.on_request_parse(&mut request)?;
+        self.expected_content_length = 0;
+        self.chunked_encoding = false;
+        match self.on_request_parse(&mut request)? {
+            Dispatch::Application => {},
+            Dispatch::InvalidTarget => {
+                // The request was rejected so the rest of it is never read.
+                self.metrics.parse_error();
+                self.state = ConnectionState::Responding;
+                return self.send_server_response(StatusCode::BAD_REQUEST, "");
+            },
+            Dispatch::Answered => {
+                self.state = ConnectionState::Responding;
+                return Ok(());
+            },
+        }
+
+        self.state = if self.chunked_encoding | (self.expected_content_length > 0) {
+            ConnectionState::ReadingBody
+        } else {
+            self.send_body(false, BytesMut::with_capacity(0))?;
+            ConnectionState::Responding
+        };
 
         Ok(())
     }
 
     fn parse_chunked_body(&mut self, buffer: &mut BytesMut) -> PyResult<()> {
         if let Some((more_body, data)) = self.drain_body_chunks(buffer)? {
-            let _ = self.receiver.send((more_body, data));
+            if !more_body {
+                self.chunked_encoding = false;
+                self.state = ConnectionState::Responding;
+            }
+
+            self.send_body(more_body, data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Hands a part of the body to the application.
+    fn send_body(&mut self, more_body: bool, data: BytesMut) -> PyResult<()> {
+        // Reading is paused as soon as the receiver fills up so there is
+        // always room for the next chunk.
+        self.receiver.send((more_body, data)).map_err(|_| {
+            PyRuntimeError::new_err("request body receiver was unexpectedly full")
+        })
+    }
+
+    /// Hands as much of the buffered body to the application as the
+    /// receiver has room for.
+    ///
+    /// Reading from the socket is paused once the receiver is full, the
+    /// rest of the body is left in the buffer until the application has
+    /// caught up and `resume_body` is called.
+    fn read_body(&mut self, buffer: &mut BytesMut) -> PyResult<()> {
+        while self.state == ConnectionState::ReadingBody {
+            if self.receiver.is_full() {
+                if !self.receiver.is_read_paused() {
+                    self.receiver.set_read_paused(true);
+                    self.metrics.backpressure(Direction::Read);
+                    self.transport()?.pause_reading()?;
+                }
+                break;
+            }
+
+            let remaining = buffer.len();
+            if self.chunked_encoding {
+                self.parse_chunked_body(buffer)?;
+            } else {
+                self.parse_body(buffer)?;
+            }
+
+            // Nothing more can be parsed until more of the body arrives.
+            if buffer.len() == remaining {
+                break;
+            }
         }
 
         Ok(())
     }
 
+    /// Resumes reading the body if it was paused and the application has
+    /// since made room in the receiver, handing over what was left in the
+    /// buffer first.
+    pub(crate) fn resume_body(&mut self, buffer: &mut BytesMut) -> PyResult<()> {
+        if !self.receiver.is_read_paused() | self.receiver.is_full() {
+            return Ok(());
+        }
+
+        self.receiver.set_read_paused(false);
+        self.transport()?.resume_reading()?;
+        self.read_body(buffer)
+    }
+
+    /// If reading has been paused until the application reads more of the
+    /// body.
+    pub(crate) fn is_body_paused(&self) -> bool {
+        self.receiver.is_read_paused()
+    }
+
     fn drain_body_chunks(
         &mut self,
         buffer: &mut BytesMut,
     ) -> PyResult<Option<(bool, BytesMut)>> {
         let mut temp_buff = BytesMut::with_capacity(FORGIVING_BUFFER_SIZE);
         loop {
-            let res = conv_err!(parse_chunk_size(&buffer))?;
-            let (start, len) = match res {
-                Status::Complete(info) => info,
-                Status::Partial => {
-                    return if temp_buff.len() <= MIN_BUFF_SIZE {
-                        Ok(None)
-                    } else {
-                        Ok(Some((true, temp_buff)))
-                    }
+            let (start, len) = match parse_chunk_size(buffer) {
+                Ok(Status::Complete(info)) => info,
+                Ok(Status::Partial) => break,
+                Err(e) => {
+                    debug!(
+                        client:% = self.transport()?.client;
+                        "closing connection, malformed request body chunk, {}",
+                        e
+                    );
+
+                    // The application may have already started responding
+                    // so the connection is closed rather than answered.
+                    buffer.clear();
+                    self.metrics.parse_error();
+                    self.keep_alive = false;
+                    self.transport()?.close()?;
+                    return Ok(None);
                 },
             };
 
             if len == 0 {
-                let _ = buffer.split_to(start + 4);
+                // The last chunk is followed by an empty line.
+                if buffer.len() < start + 2 {
+                    break;
+                }
+
+                let _ = buffer.split_to(start + 2);
                 return Ok(Some((false, temp_buff)));
             }
 
+            // Wait for the whole chunk and its \r\n suffix to arrive.
+            if buffer.len() < start + len as usize + 2 {
+                break;
+            }
+
             let _ = buffer.split_to(start);
             let body = buffer.split_to(len as usize);
             let _ = buffer.split_to(2); // remove \r\n suffix
@@ -248,11 +680,19 @@ impl H1Protocol {
                 return Ok(Some((true, temp_buff)));
             }
         }
+
+        if temp_buff.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some((true, temp_buff)))
+        }
     }
 
     fn parse_body(&mut self, buffer: &mut BytesMut) -> PyResult<()> {
         let (more_body, data) = if buffer.len() >= self.expected_content_length {
             let res = buffer.split_to(self.expected_content_length);
+            self.expected_content_length = 0;
+            self.state = ConnectionState::Responding;
             (false, Some(res))
         } else if buffer.len() >= MIN_BUFF_SIZE {
             let res = buffer.clone();
@@ -264,7 +704,7 @@ impl H1Protocol {
         };
 
         if let Some(data) = data {
-            let _ = self.receiver.send((more_body, data));
+            self.send_body(more_body, data)?;
         }
 
         Ok(())
@@ -272,7 +712,10 @@ impl H1Protocol {
 
     /// Turns all the headers into Python type objects and invokes the
     /// python callback.
-    fn on_request_parse(&mut self, request: &mut Request) -> PyResult<()> {
+    ///
+    /// The callback is not invoked if the request target is invalid or the
+    /// request is for one of the probe endpoints answered by the server.
+    fn on_request_parse(&mut self, request: &mut Request) -> PyResult<Dispatch> {
         let method = request.method.expect("Method was None at complete parse");
         let path = request.path.expect("Path was None at complete parse");
         let version = request.version.expect("Version was None at complete parse");
@@ -287,45 +730,167 @@ impl H1Protocol {
             unreachable!()
         };
 
-        let uri = path.parse::<Uri>().expect("failed to parse http url");
+        // Proxy headers are only honoured if they come from a trusted proxy.
+        let trusted = forwarded::is_trusted(
+            &self.settings.trusted_proxies,
+            self.transport()?.client.ip(),
+        );
+        let request_id = resolve_request_id(request.headers, trusted);
+
+        self.request_started = Some((method.to_string(), Instant::now()));
+        self.response_status = None;
+
+        // Created within the connection's span which is entered while the
+        // connection is being polled.
+        let span = info_span!(
+            "request",
+            method,
+            path,
+            request_id = request_id.as_str(),
+            status = field::Empty,
+        );
+        let _entered = span.clone().entered();
+        self.request_span = Some(span);
+
+        self.access = if log_enabled!(target: ACCESS_LOG_TARGET, Level::Info) {
+            let header_count = self.settings.access_log_format.headers().len();
+            let mut record = AccessRecord::new(method, path, version, header_count);
+
+            // Replaced by the address given by a trusted proxy if there is one.
+            record.set_client(&self.transport()?.client.ip().to_string());
+            record.set_request_id(&request_id);
+            Some(record)
+        } else {
+            None
+        };
+
+        self.requests_handled += 1;
+        if let Some(max) = self.settings.max_requests_per_connection {
+            if self.requests_handled >= max {
+                self.keep_alive = false;
+            }
+        }
+
+        let target = match RequestTarget::parse(method, path) {
+            Some(target) => target,
+            None => {
+                debug!(
+                    client:% = self.transport()?.client,
+                    request_id = request_id.as_str();
+                    "rejecting request with invalid target {:?}",
+                    path
+                );
+                return Ok(Dispatch::InvalidTarget);
+            },
+        };
+
+        if self.answer_probe(method, &target.path, request.headers)? {
+            return Ok(Dispatch::Answered);
+        }
 
-        let headers_new = Python::with_gil(|py| {
-            let mut parsed_vec = Vec::with_capacity(request.headers.len());
-            for header in request.headers.iter() {
-                self.check_header(&header);
+        let mut proxy_headers = ForwardedHeaders::default();
+        let mut forwarded_prefix = None;
+        let mut headers = Vec::with_capacity(request.headers.len());
+        for header in request.headers.iter() {
+            self.check_header(header);
 
-                let bytes_body = PyBytes::new(py, header.value.as_ref());
+            if trusted {
+                proxy_headers.check_header(header);
 
-                let converted2: Py<PyBytes> = Py::from(bytes_body);
-                parsed_vec.push((header.name, converted2))
+                if self.settings.forwarded_prefix
+                    & header.name.eq_ignore_ascii_case(X_FORWARDED_PREFIX)
+                {
+                    forwarded_prefix = str::from_utf8(header.value).ok();
+                }
             }
 
-            parsed_vec
-        });
+            if let Some(record) = self.access.as_mut() {
+                let names = self.settings.access_log_format.headers();
+                for (index, name) in names.iter().enumerate() {
+                    if header.name.eq_ignore_ascii_case(name) {
+                        record.set_header(index, header.value);
+                    }
+                }
+            }
+
+            headers.push((header.name.as_bytes(), header.value));
+        }
+
+        let root_path = match forwarded_prefix {
+            Some(prefix) => prefix.trim_end_matches('/'),
+            None => self.settings.root_path.as_str(),
+        };
 
-        let transport = self.transport()?;
-        let server = (transport.server.ip().to_string(), transport.server.port());
-        let client = (transport.client.ip().to_string(), transport.client.port());
-        let schema = if transport.tls { "https" } else { "http" };
+        let transport = self.transport()?.clone();
+        let mut server = (transport.server.ip().to_string(), transport.server.port());
+        let mut client = (transport.client.ip().to_string(), transport.client.port());
+        let mut scheme = if transport.tls { "https" } else { "http" };
 
-        let scope: lsgi::LSGIScope = (
-            lsgi::SCOPE_TYPE,
-            version,
+        if trusted {
+            let details = proxy_headers.resolve(&self.settings.trusted_proxies);
+            client = details.client.unwrap_or(client);
+            server = details.server.unwrap_or(server);
+            scheme = details.scheme.unwrap_or(scheme);
+        }
+
+        if let Some(record) = self.access.as_mut() {
+            record.set_client(&client.0);
+        }
+
+        let scope = lsgi::LSGIScope {
+            http_version: version,
             method,
-            schema,
-            uri.path(),
-            uri.query().unwrap_or(""),
-            lsgi::TEMP_ROOT_PATH,
-            headers_new,
-            server,
+            scheme,
+            path: &target.path,
+            raw_path: target.raw_path.as_bytes(),
+            query_string: target.query.as_bytes(),
+            root_path,
+            headers,
             client,
-        );
+            server,
+            request_id: &request_id,
+        };
 
-        let sender = self.sender.make_handle();
-        let receiver = self.receiver.make_handle();
-        self.callback.invoke((scope, sender, receiver))?;
+        let keep_alive = if self.keep_alive {
+            Some(self.settings.keep_alive.as_secs())
+        } else {
+            None
+        };
 
-        Ok(())
+        // Lets the stall detector report which requests may be blocking
+        // the event loop.
+        let in_flight = InFlight {
+            conn: transport.index(),
+            client: transport.client,
+            method: method.to_string(),
+            path: target.path.to_string(),
+            request_id: request_id.clone(),
+            started: Instant::now(),
+        };
+        self.in_flight = Some(self.health.request_started(in_flight));
+
+        let echoed_id = if self.settings.echo_request_id {
+            Some(request_id.clone())
+        } else {
+            None
+        };
+
+        // Each request gets its own channels so nothing the previous
+        // request's application left unread or sent late reaches this one.
+        self.sender = SenderFactory::new();
+        self.receiver = ReceiverFactory::new();
+        let receiver = self.receiver.make_handle(transport.clone());
+        let sender = self
+            .sender
+            .make_handle(method, keep_alive, transport, echoed_id);
+        let task = Python::with_gil(|py| -> PyResult<Option<PyObject>> {
+            let scope = scope.to_dict(py)?;
+            let task = self.callback.invoke((scope, sender, receiver))?;
+            Ok(if task.is_none(py) { None } else { Some(task) })
+        })?;
+        self.task = task;
+
+        Ok(Dispatch::Application)
     }
 
     /// Checks a given header to see if it is to do with the request's
@@ -343,3 +908,40 @@ impl H1Protocol {
         }
     }
 }
+
+/// The ID of the request, reusing the one given by a trusted proxy if it is
+/// reasonably sized and printable, otherwise a new random UUID.
+fn resolve_request_id(headers: &[Header], trusted: bool) -> String {
+    if trusted {
+        let given = headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(X_REQUEST_ID))
+            .map(|header| header.value)
+            .filter(|value| !value.is_empty() & (value.len() <= MAX_REQUEST_ID_LEN))
+            .filter(|value| value.iter().all(u8::is_ascii_graphic));
+
+        if let Some(value) = given {
+            return String::from_utf8_lossy(value).into_owned();
+        }
+    }
+
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// Cancels the given asyncio task, waiting for the grace period first if
+/// one is set.
+fn cancel_task(py: Python, task: PyObject, grace_period: Duration) -> PyResult<()> {
+    if grace_period == Duration::ZERO {
+        task.call_method0(py, "cancel")?;
+        return Ok(());
+    }
+
+    let cancel = task.getattr(py, "cancel")?;
+    task.call_method0(py, "get_loop")?.call_method1(
+        py,
+        "call_later",
+        (grace_period.as_secs_f64(), cancel),
+    )?;
+
+    Ok(())
+}
@@ -1,5 +1,6 @@
 mod h1;
 mod selector;
+mod target;
 
-pub(crate) use h1::H1Protocol;
-pub(crate) use selector::{AutoProtocol, Protocols};
+pub(crate) use h1::{server_response, H1Protocol};
+pub(crate) use selector::{AutoProtocol, ConnectionState, Protocols};
@@ -1,7 +1,9 @@
 use bytes::BytesMut;
-use pyo3::PyResult;
+use pyo3::{PyObject, PyResult, Python};
 
 use super::H1Protocol;
+use crate::health::SharedHealth;
+use crate::metrics::SharedMetrics;
 use crate::server::CallbackHandler;
 use crate::settings::Settings;
 use crate::traits::{BaseTransport, BufferHandler, ProtocolBuffers, SocketState};
@@ -22,6 +24,23 @@ pub(crate) enum SwitchStatus {
     NoSwitch,
 }
 
+/// The stage of the request / response cycle a connection is currently at.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum ConnectionState {
+    /// Waiting for the first bytes of a new request.
+    Idle,
+
+    /// Part of the request head has been received but not all of it.
+    ReadingHead,
+
+    /// The head has been parsed and the body is still being received.
+    ReadingBody,
+
+    /// The request has been handed to the application and the response
+    /// has not yet completed.
+    Responding,
+}
+
 pub(crate) struct AutoProtocol {
     transport: Transport,
 
@@ -38,8 +57,10 @@ impl AutoProtocol {
         selected: Protocols,
         transport: Transport,
         callback: CallbackHandler,
+        metrics: SharedMetrics,
+        health: SharedHealth,
     ) -> Self {
-        let mut h1 = H1Protocol::new(settings, callback);
+        let mut h1 = H1Protocol::new(settings, callback, metrics, health);
         h1.new_connection(transport.clone());
 
         Self {
@@ -61,6 +82,57 @@ impl AutoProtocol {
         }
     }
 
+    /// The current stage of the request / response cycle.
+    pub(crate) fn state(&self) -> ConnectionState {
+        match self.selected {
+            Protocols::H1 => self.h1.state(),
+        }
+    }
+
+    /// If reading has been paused until the application reads more of the
+    /// request body.
+    pub(crate) fn is_body_paused(&self) -> bool {
+        match self.selected {
+            Protocols::H1 => self.h1.is_body_paused(),
+        }
+    }
+
+    /// If there is any data waiting to be written to the socket.
+    pub(crate) fn has_pending_writes(&self) -> bool {
+        !self.writer_buffer.is_empty()
+    }
+
+    /// The task handling the current request if it is still running.
+    pub(crate) fn pending_task(&self, py: Python) -> Option<PyObject> {
+        match self.selected {
+            Protocols::H1 => self.h1.pending_task(py),
+        }
+    }
+
+    /// Replaces any pending response with a `408 Request Timeout` and
+    /// marks the connection to be closed once it has been written.
+    pub(crate) fn request_timed_out(&mut self) -> PyResult<()> {
+        self.writer_buffer.clear();
+        match self.selected {
+            Protocols::H1 => self.h1.request_timed_out(),
+        }
+    }
+
+    /// If the application has finished without completing the response.
+    pub(crate) fn response_abandoned(&self, py: Python) -> bool {
+        match self.selected {
+            Protocols::H1 => self.h1.response_abandoned(py),
+        }
+    }
+
+    /// Gives up on the response to the current request, see
+    /// `H1Protocol::abandon_response`.
+    pub(crate) fn abandon_response(&mut self) -> PyResult<()> {
+        match self.selected {
+            Protocols::H1 => self.h1.abandon_response(),
+        }
+    }
+
     /// Pauses reading from the event loop and notifies the protocol of
     /// the pause to allow the protocol to re-wake the state later on.
     fn pause_writing(&mut self) -> PyResult<()> {
@@ -108,6 +180,7 @@ impl BufferHandler for AutoProtocol {
     fn write_buffer_acquire(&mut self) -> PyResult<&mut BytesMut> {
         match self.selected {
             Protocols::H1 => {
+                self.h1.resume_body(&mut self.reader_buffer)?;
                 self.h1.fill_write_buffer(&mut self.writer_buffer)?;
             },
         };
@@ -116,7 +189,7 @@ impl BufferHandler for AutoProtocol {
     }
 
     fn write_buffer_drained(&mut self, amount: usize) -> PyResult<()> {
-        if (amount == 0) | (self.writer_buffer.len() == 0) {
+        if (amount == 0) | self.writer_buffer.is_empty() {
             self.pause_writing()?;
         }
 
@@ -0,0 +1,228 @@
+use std::borrow::Cow;
+
+/// A parsed HTTP/1.x request target.
+///
+/// Covers every form defined in RFC 7230 section 5.3, the raw path is kept
+/// exactly as it was received while the decoded path has any percent-encoded
+/// sequences decoded into UTF-8 characters.
+pub(crate) struct RequestTarget<'a> {
+    /// The path component exactly as it was sent by the client.
+    pub raw_path: &'a str,
+
+    /// The path component with percent-encoded sequences decoded.
+    pub path: Cow<'a, str>,
+
+    /// The still percent-encoded portion of the target after the `?`.
+    pub query: &'a str,
+}
+
+impl<'a> RequestTarget<'a> {
+    /// Parses the given request target returning `None` if it is not a
+    /// valid target.
+    ///
+    /// - origin-form: `/where?q=now`
+    /// - absolute-form: `http://www.example.org/where?q=now`
+    /// - authority-form: `www.example.com:80`, only valid for `CONNECT`
+    /// - asterisk-form: `*`, only valid for `OPTIONS`
+    pub(crate) fn parse(method: &str, target: &'a str) -> Option<Self> {
+        if target == "*" {
+            return if method == "OPTIONS" {
+                Some(Self::from_path(target, ""))
+            } else {
+                None
+            };
+        }
+
+        if method == "CONNECT" {
+            return if is_authority(target) {
+                Some(Self::from_path(target, ""))
+            } else {
+                None
+            };
+        }
+
+        let origin = if target.starts_with('/') {
+            target
+        } else {
+            strip_authority(target)?
+        };
+
+        if origin.contains('#') {
+            return None;
+        }
+
+        let (raw_path, query) = match origin.find('?') {
+            Some(index) => (&origin[..index], &origin[index + 1..]),
+            None => (origin, ""),
+        };
+
+        // An absolute target with no path refers to the root.
+        let raw_path = if raw_path.is_empty() { "/" } else { raw_path };
+
+        Some(Self::from_path(raw_path, query))
+    }
+
+    /// Creates a target from an already separated path and query.
+    fn from_path(raw_path: &'a str, query: &'a str) -> Self {
+        Self {
+            raw_path,
+            path: percent_decode(raw_path),
+            query,
+        }
+    }
+}
+
+/// Removes the `scheme://authority` prefix of an absolute-form target,
+/// returning the remaining path and query.
+fn strip_authority(target: &str) -> Option<&str> {
+    let index = target.find("://")?;
+    let scheme = &target[..index];
+    if !(scheme.eq_ignore_ascii_case("http") | scheme.eq_ignore_ascii_case("https")) {
+        return None;
+    }
+
+    let rest = &target[index + 3..];
+    let end = rest.find(|c| (c == '/') | (c == '?')).unwrap_or(rest.len());
+    if end == 0 {
+        return None;
+    }
+
+    Some(&rest[end..])
+}
+
+/// If the target is a valid `host:port` authority.
+fn is_authority(target: &str) -> bool {
+    match target.rfind(':') {
+        Some(index) => {
+            let port = &target[index + 1..];
+            (index > 0)
+                & !port.is_empty()
+                & port.bytes().all(|b| b.is_ascii_digit())
+                & !target.contains(|c| (c == '/') | (c == '?') | (c == '#'))
+        },
+        None => false,
+    }
+}
+
+/// Decodes any percent-encoded sequences in the given path.
+///
+/// Invalid escape sequences are kept as-is and any invalid UTF-8 produced
+/// by decoding is replaced with the unicode replacement character.
+fn percent_decode(path: &str) -> Cow<'_, str> {
+    if !path.contains('%') {
+        return Cow::Borrowed(path);
+    }
+
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if (bytes[i] == b'%') & (i + 2 < bytes.len()) {
+            if let (Some(hi), Some(lo)) =
+                (hex_value(bytes[i + 1]), hex_value(bytes[i + 2]))
+            {
+                decoded.push((hi << 4) | lo);
+                i += 3;
+                continue;
+            }
+        }
+
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    let decoded = String::from_utf8(decoded)
+        .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned());
+
+    Cow::Owned(decoded)
+}
+
+/// The value of a single hex digit.
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origin_form_is_split_and_decoded() {
+        let target = RequestTarget::parse("GET", "/caf%C3%A9/a%20b?x=1&y=%20").unwrap();
+        assert_eq!(target.raw_path, "/caf%C3%A9/a%20b");
+        assert_eq!(target.path, "/café/a b");
+        assert_eq!(target.query, "x=1&y=%20");
+    }
+
+    #[test]
+    fn origin_form_without_escapes_is_borrowed() {
+        let target = RequestTarget::parse("GET", "/plain").unwrap();
+        assert!(matches!(target.path, Cow::Borrowed("/plain")));
+        assert_eq!(target.query, "");
+    }
+
+    #[test]
+    fn absolute_form_keeps_only_the_path_and_query() {
+        let target =
+            RequestTarget::parse("GET", "http://example.org/where?q=now").unwrap();
+        assert_eq!(target.raw_path, "/where");
+        assert_eq!(target.query, "q=now");
+
+        let target = RequestTarget::parse("GET", "HTTPS://example.org:8443").unwrap();
+        assert_eq!(target.raw_path, "/");
+
+        let target = RequestTarget::parse("GET", "http://example.org?q").unwrap();
+        assert_eq!(target.raw_path, "/");
+        assert_eq!(target.query, "q");
+    }
+
+    #[test]
+    fn absolute_form_rejects_other_schemes_and_missing_hosts() {
+        assert!(RequestTarget::parse("GET", "ftp://example.org/").is_none());
+        assert!(RequestTarget::parse("GET", "http:///where").is_none());
+        assert!(RequestTarget::parse("GET", "example.org/where").is_none());
+    }
+
+    #[test]
+    fn authority_form_is_only_valid_for_connect() {
+        let target = RequestTarget::parse("CONNECT", "example.com:443").unwrap();
+        assert_eq!(target.raw_path, "example.com:443");
+
+        assert!(RequestTarget::parse("CONNECT", "example.com").is_none());
+        assert!(RequestTarget::parse("CONNECT", ":443").is_none());
+        assert!(RequestTarget::parse("CONNECT", "example.com:").is_none());
+        assert!(RequestTarget::parse("CONNECT", "/path").is_none());
+        assert!(RequestTarget::parse("GET", "example.com:443").is_none());
+    }
+
+    #[test]
+    fn asterisk_form_is_only_valid_for_options() {
+        let target = RequestTarget::parse("OPTIONS", "*").unwrap();
+        assert_eq!(target.raw_path, "*");
+        assert!(RequestTarget::parse("GET", "*").is_none());
+    }
+
+    #[test]
+    fn fragments_are_rejected() {
+        assert!(RequestTarget::parse("GET", "/where#here").is_none());
+        assert!(RequestTarget::parse("GET", "/where?q=1#here").is_none());
+    }
+
+    #[test]
+    fn invalid_escapes_are_kept() {
+        assert_eq!(percent_decode("/%zz/%4"), "/%zz/%4");
+        assert_eq!(percent_decode("/100%"), "/100%");
+        assert_eq!(percent_decode("/%41%4a%4A"), "/AJJ");
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        assert_eq!(percent_decode("/%ff"), "/\u{fffd}");
+    }
+}
@@ -0,0 +1,247 @@
+use pyo3::class::iter::IterNextOutput;
+use pyo3::prelude::*;
+use pyo3::types::PyTuple;
+use pyo3::{PyAsyncProtocol, PyClass, PyIterProtocol, PyNativeType};
+
+use super::{DataReceiver, DataSender, ReceiverPayload, SenderPayload};
+
+/// The result of polling an awaitable, either the future to yield to the
+/// running task or the value the awaitable resolved to.
+pub(crate) type Poll<T> = PyResult<IterNextOutput<PyObject, T>>;
+
+/// Creates a future on the running event loop and a waker resolving it.
+///
+/// The future is marked as blocking so it can be yielded directly to the
+/// task driving the awaitable.
+pub(crate) fn create_waiter(py: Python) -> PyResult<(PyObject, PyObject)> {
+    let fut: PyObject = py
+        .import("asyncio")?
+        .call_method0("get_running_loop")?
+        .call_method0("create_future")?
+        .into();
+    fut.as_ref(py).setattr("_asyncio_future_blocking", true)?;
+
+    let waker = Py::new(
+        py,
+        Waker {
+            fut: fut.clone_ref(py),
+        },
+    )?;
+
+    Ok((fut, waker.into_py(py)))
+}
+
+/// An awaitable implemented in Rust and driven by Python calling
+/// `__next__` until it returns.
+///
+/// While it cannot make progress the awaitable yields a future to the task
+/// driving it, the future is re-yielded until it is resolved by a waker
+/// and only then is the awaitable polled again.
+pub(crate) trait Awaitable {
+    /// The value the awaitable resolves to.
+    type Output: IntoPy<PyObject>;
+
+    /// The future the awaitable is waiting on, if any.
+    fn waiter(&mut self) -> &mut Option<PyObject>;
+
+    /// Attempts to make progress, any future yielded before has resolved.
+    fn poll_ready(&mut self, py: Python) -> Poll<Self::Output>;
+
+    /// Polls the awaitable, re-yielding the future it is waiting on if it
+    /// has not yet been resolved.
+    fn poll(&mut self, py: Python) -> Poll<Self::Output> {
+        let waiter = self.waiter();
+        if let Some(fut) = waiter.as_ref() {
+            if !fut.call_method0(py, "done")?.extract::<bool>(py)? {
+                return Ok(IterNextOutput::Yield(fut.clone_ref(py)));
+            }
+        }
+
+        *waiter = None;
+        self.poll_ready(py)
+    }
+
+    /// Waits on the given future, yielding it to the task driving the
+    /// awaitable.
+    fn wait_on(&mut self, py: Python, fut: PyObject) -> Poll<Self::Output> {
+        *self.waiter() = Some(fut.clone_ref(py));
+        Ok(IterNextOutput::Yield(fut))
+    }
+}
+
+/// Advances the awaitable for its `__next__`, converting what it resolves
+/// to into a Python object.
+///
+/// The GIL token is taken from the cell Python is advancing.
+pub(crate) fn advance<T>(slf: &PyCell<T>) -> Poll<PyObject>
+where
+    T: Awaitable + PyClass,
+{
+    let py = slf.py();
+    Ok(match slf.try_borrow_mut()?.poll(py)? {
+        IterNextOutput::Yield(fut) => IterNextOutput::Yield(fut),
+        IterNextOutput::Return(value) => IterNextOutput::Return(value.into_py(py)),
+    })
+}
+
+/// A callback resolving a future when invoked with any arguments.
+#[pyclass]
+pub struct Waker {
+    fut: PyObject,
+}
+
+#[pymethods]
+impl Waker {
+    #[call]
+    #[args(_args = "*")]
+    fn __call__(&self, py: Python, _args: &PyTuple) -> PyResult<()> {
+        // The task waiting on the future may have been cancelled.
+        if self.fut.call_method0(py, "done")?.extract(py)? {
+            return Ok(());
+        }
+
+        self.fut.call_method1(py, "set_result", (py.None(),))?;
+        Ok(())
+    }
+}
+
+/// The awaitable returned by the `DataReceiver`, resolving to the next
+/// chunk of the request body.
+#[pyclass]
+pub struct ReceiveAwaitable {
+    receiver: Py<DataReceiver>,
+
+    /// The chunk if one was available when the awaitable was created.
+    ready: Option<ReceiverPayload>,
+
+    /// The future yielded while waiting for a chunk.
+    waiter: Option<PyObject>,
+}
+
+impl ReceiveAwaitable {
+    pub(crate) fn new(
+        receiver: Py<DataReceiver>,
+        ready: Option<ReceiverPayload>,
+    ) -> Self {
+        Self {
+            receiver,
+            ready,
+            waiter: None,
+        }
+    }
+}
+
+impl Awaitable for ReceiveAwaitable {
+    type Output = ReceiverPayload;
+
+    fn waiter(&mut self) -> &mut Option<PyObject> {
+        &mut self.waiter
+    }
+
+    /// Attempts to receive a chunk, subscribing a waker to the receiver if
+    /// none are available.
+    fn poll_ready(&mut self, py: Python) -> Poll<ReceiverPayload> {
+        if let Some(payload) = self.ready.take() {
+            return Ok(IterNextOutput::Return(payload));
+        }
+
+        let receiver = self.receiver.borrow(py);
+        if let Some(payload) = receiver.try_recv()? {
+            return Ok(IterNextOutput::Return(payload));
+        }
+
+        let (fut, waker) = create_waiter(py)?;
+        receiver.subscribe(waker);
+        drop(receiver);
+
+        self.wait_on(py, fut)
+    }
+}
+
+#[pyproto]
+impl PyAsyncProtocol for ReceiveAwaitable {
+    fn __await__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+}
+
+#[pyproto]
+impl PyIterProtocol for ReceiveAwaitable {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __next__(slf: &PyCell<Self>) -> Poll<PyObject> {
+        advance(slf)
+    }
+}
+
+/// The awaitable returned by the `DataSender`, resolving once the payload
+/// has been handed to the server handler.
+#[pyclass]
+pub struct SendAwaitable {
+    sender: Py<DataSender>,
+
+    /// The payload if it could not be sent when the awaitable was created.
+    payload: Option<SenderPayload>,
+
+    /// The future yielded while waiting for the handler to drain.
+    waiter: Option<PyObject>,
+}
+
+impl SendAwaitable {
+    pub(crate) fn new(sender: Py<DataSender>, payload: Option<SenderPayload>) -> Self {
+        Self {
+            sender,
+            payload,
+            waiter: None,
+        }
+    }
+}
+
+impl Awaitable for SendAwaitable {
+    type Output = ();
+
+    fn waiter(&mut self) -> &mut Option<PyObject> {
+        &mut self.waiter
+    }
+
+    /// Attempts to send the payload, subscribing a waker to the sender if
+    /// the channel is full.
+    fn poll_ready(&mut self, py: Python) -> Poll<()> {
+        let payload = match self.payload.take() {
+            Some(payload) => payload,
+            None => return Ok(IterNextOutput::Return(())),
+        };
+
+        let sender = self.sender.borrow(py);
+        self.payload = sender.try_send(payload)?;
+        if self.payload.is_none() {
+            return Ok(IterNextOutput::Return(()));
+        }
+
+        let (fut, waker) = create_waiter(py)?;
+        sender.subscribe(waker);
+        drop(sender);
+
+        self.wait_on(py, fut)
+    }
+}
+
+#[pyproto]
+impl PyAsyncProtocol for SendAwaitable {
+    fn __await__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+}
+
+#[pyproto]
+impl PyIterProtocol for SendAwaitable {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __next__(slf: &PyCell<Self>) -> Poll<PyObject> {
+        advance(slf)
+    }
+}
@@ -1,12 +1,17 @@
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 
 use crossbeam::queue::SegQueue;
+use pyo3::exceptions::PyConnectionResetError;
 use pyo3::types::PyBytes;
-use pyo3::{Py, PyObject};
+use pyo3::{Py, PyErr, PyObject, Python};
 
+mod awaitable;
 mod receiver;
 mod sender;
 
+pub(crate) use awaitable::{advance, create_waiter, Awaitable, Poll};
+pub use awaitable::{ReceiveAwaitable, SendAwaitable};
 pub use receiver::{DataReceiver, ReceiverFactory};
 pub use sender::{DataSender, SenderFactory};
 
@@ -20,3 +25,20 @@ pub type ReceiverPayload = (bool, Py<PyBytes>);
 
 /// The queue of Python waiters to be woken up on a given event.
 pub(crate) type WakerQueue = Arc<SegQueue<PyObject>>;
+
+/// A flag set once the client of a connection has disconnected.
+pub(crate) type DisconnectFlag = Arc<AtomicBool>;
+
+/// Invokes and removes every waker in the queue, any errors raised by the
+/// wakers are ignored.
+pub(crate) fn wake_all(py: Python, queue: &WakerQueue) {
+    while let Some(waker) = queue.pop() {
+        let _ = waker.call0(py);
+    }
+}
+
+/// The error raised when interacting with a request whose client has
+/// disconnected.
+pub(crate) fn disconnected_error() -> PyErr {
+    PyConnectionResetError::new_err("the client has disconnected")
+}
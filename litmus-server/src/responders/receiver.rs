@@ -1,13 +1,23 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use bytes::BytesMut;
 use crossbeam::channel::{bounded, Receiver, Sender, TryRecvError, TrySendError};
 use crossbeam::queue::SegQueue;
-use pyo3::exceptions::{PyBlockingIOError, PyRuntimeError};
+use pyo3::exceptions::PyRuntimeError;
 use pyo3::prelude::*;
 use pyo3::types::PyBytes;
 
-use super::{ReceiverPayload, WakerQueue};
+use super::{
+    disconnected_error,
+    wake_all,
+    DisconnectFlag,
+    ReceiveAwaitable,
+    ReceiverPayload,
+    WakerQueue,
+};
+use crate::traits::BaseTransport;
+use crate::transport::Transport;
 
 /// The callable class that handling communication back to the server protocol.
 #[pyclass]
@@ -18,67 +28,103 @@ pub struct DataReceiver {
     /// A queue of waiting events to invoke before the body
     /// can be read from the receiver again.
     waiter_queue: WakerQueue,
+
+    /// Set once the client has disconnected.
+    disconnected: DisconnectFlag,
+
+    /// Set while the protocol has stopped reading the body because the
+    /// receiver is full.
+    read_paused: Arc<AtomicBool>,
+
+    /// The transport of the connection the body is read from.
+    transport: Transport,
 }
 
 impl DataReceiver {
     /// Create a new handler with the given sender.
-    pub fn new(rx: Receiver<ReceiverPayload>, waiter_queue: WakerQueue) -> Self {
-        Self { rx, waiter_queue }
+    pub fn new(
+        rx: Receiver<ReceiverPayload>,
+        waiter_queue: WakerQueue,
+        disconnected: DisconnectFlag,
+        read_paused: Arc<AtomicBool>,
+        transport: Transport,
+    ) -> Self {
+        Self {
+            rx,
+            waiter_queue,
+            disconnected,
+            read_paused,
+            transport,
+        }
+    }
+
+    /// Receives a chunk of data from the socket without blocking, returning
+    /// `None` if no chunk is available yet.
+    ///
+    /// Chunks received before the client disconnected are still returned,
+    /// after that a `ConnectionResetError` is raised.
+    pub(crate) fn try_recv(&self) -> PyResult<Option<ReceiverPayload>> {
+        match self.rx.try_recv() {
+            Ok(payload) => {
+                // There is room for more of the body, the protocol is woken
+                // through the writer to hand over what it has held back.
+                if self.read_paused.load(Ordering::Relaxed) {
+                    self.transport.resume_writing()?;
+                }
+
+                Ok(Some(payload))
+            },
+            Err(TryRecvError::Empty) if self.is_disconnected() => {
+                Err(disconnected_error())
+            },
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(PyRuntimeError::new_err(
+                "receiving channel was unexpectedly closed.",
+            )),
+        }
+    }
+
+    /// Submits a given callback to the waiter queue.
+    ///
+    /// Any waiters in the queue when a chunk of the body is sent to the
+    /// receiver or the client disconnects are taken out of the queue and invoked with no arguments,
+    /// any errors raised by a waker are ignored.
+    pub(crate) fn subscribe(&self, waker: PyObject) {
+        self.waiter_queue.push(waker);
     }
 }
 
 #[pymethods]
 impl DataReceiver {
-    /// Receives a chunk of data from the socket without blocking.
-    ///
-    /// Invoked by python passing more_body which represents if there
-    /// is any more body to expect or not, and the body itself.
+    /// If the client has disconnected.
+    pub(crate) fn is_disconnected(&self) -> bool {
+        self.disconnected.load(Ordering::Relaxed)
+    }
+
+    /// Receives a chunk of the request body.
     ///
     /// Returns:
-    ///     A tuple containing a boolean and a set of bytes, the boolean signals
-    ///     if there is more data to be read from the socket or not and the
-    ///     bytes returned are what contain the actual data.
+    /// ```text
+    ///     An awaitable resolving to a tuple containing a boolean and a set
+    ///     of bytes, the boolean signals if there is more data to be read
+    ///     from the socket or not and the bytes returned are what contain
+    ///     the actual data.
+    /// ```
     ///
     /// Raises:
+    /// ```text
     ///     RuntimeError:
     ///         If the channel the receiver uses to communicate with the main
     ///         socket handler is closed.
     ///
-    ///     BlockingIOError:
-    ///         The receiver is empty and would block waiting for data to be
-    ///         sent to the receiver. In the event that this error is raised
-    ///         the handler should set a waker in order to be notified when
-    ///         data is available.
+    ///     ConnectionResetError:
+    ///         If the client disconnected before the rest of the body was
+    ///         received.
+    /// ```
     #[call]
-    fn __call__(&self) -> PyResult<(bool, Py<PyBytes>)> {
-        let resp = self.rx.try_recv();
-
-        return match resp {
-            Ok(values) => Ok(values),
-            Err(TryRecvError::Disconnected) => Err(PyRuntimeError::new_err(
-                "receiving channel was unexpectedly closed.",
-            )),
-            Err(TryRecvError::Empty) => Err(PyBlockingIOError::new_err(())),
-        };
-    }
-
-    /// Submits a given callback to the waiter queue.
-    ///
-    /// Any waiters in the queue when the socket is able to be read from will
-    /// be taken out of the queue and invoked signalling the system's ability
-    /// to be read from again.
-    ///
-    /// All waker callbacks are invoked with no parameters or key word
-    /// arguments and are expected not to directly raise an error, in the case
-    /// that a waker does raise an error the exception is ignored and
-    /// implicitly silenced.
-    ///
-    /// Args:
-    ///     waker:
-    ///         A callback to be invoked when data can be read from the socket
-    ///         without blocking.
-    fn subscribe(&self, waker: PyObject) {
-        self.waiter_queue.push(waker);
+    fn __call__(slf: PyRef<Self>) -> PyResult<ReceiveAwaitable> {
+        let ready = slf.try_recv()?;
+        Ok(ReceiveAwaitable::new(slf.into(), ready))
     }
 }
 
@@ -94,6 +140,18 @@ pub struct ReceiverFactory {
     /// A queue of waiting events to invoke before the body
     /// can be read from the receiver again.
     waiter_queue: WakerQueue,
+
+    /// Set once the client has disconnected.
+    disconnected: DisconnectFlag,
+
+    /// Set while reading is paused until the receiver has room.
+    read_paused: Arc<AtomicBool>,
+}
+
+impl Default for ReceiverFactory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ReceiverFactory {
@@ -106,20 +164,44 @@ impl ReceiverFactory {
             receiver_tx: tx,
             receiver_rx: rx,
             waiter_queue: queue,
+            disconnected: Arc::new(AtomicBool::new(false)),
+            read_paused: Arc::new(AtomicBool::new(false)),
         }
     }
 
-    /// Makes a new sending handle with the given factory channels and queue.
-    pub fn make_handle(&self) -> DataReceiver {
-        DataReceiver::new(self.receiver_rx.clone(), self.waiter_queue.clone())
+    /// Makes a new receiving handle with the given factory channels and
+    /// queue, waking the protocol through the transport once the body can
+    /// be read again.
+    pub fn make_handle(&self, transport: Transport) -> DataReceiver {
+        DataReceiver::new(
+            self.receiver_rx.clone(),
+            self.waiter_queue.clone(),
+            self.disconnected.clone(),
+            self.read_paused.clone(),
+            transport,
+        )
+    }
+
+    /// If the receiver cannot take another chunk of the body until the
+    /// application reads one.
+    pub fn is_full(&self) -> bool {
+        self.receiver_tx.is_full()
+    }
+
+    /// If reading is paused until the receiver has room.
+    pub fn is_read_paused(&self) -> bool {
+        self.read_paused.load(Ordering::Relaxed)
+    }
+
+    /// Marks reading as paused or resumed.
+    pub fn set_read_paused(&self, paused: bool) {
+        self.read_paused.store(paused, Ordering::Relaxed);
     }
 
     /// Sends the given payload to the handler channel.
     ///
     /// This implicitly wakes up any waiters waiting for a chunk of data
-    /// from the handler, unlike the receiver version of this responder
-    /// this will only pop one waiter from the queue and pass it the chunk
-    /// of data vs waking all waiters.
+    /// from the handler.
     pub fn send(
         &self,
         data: (bool, BytesMut),
@@ -129,15 +211,17 @@ impl ReceiverFactory {
                 unsafe { PyBytes::from_ptr(py, data.1.as_ptr(), data.1.len()) };
             let body = Py::from(bytes_body);
 
-            if self.waiter_queue.len() > 0 {
-                if let Some(waker) = self.waiter_queue.pop() {
-                    // The waker should not affect the writer
-                    let _ = waker.call1(py, (data.0, body));
-                }
-                Ok(())
-            } else {
-                self.receiver_tx.try_send((data.0, body))
-            }
+            self.receiver_tx.try_send((data.0, body))?;
+            wake_all(py, &self.waiter_queue);
+
+            Ok(())
         })
     }
+
+    /// Marks the client as disconnected, waking any waiters so they can
+    /// observe the disconnect.
+    pub fn disconnect(&self) {
+        self.disconnected.store(true, Ordering::Relaxed);
+        Python::with_gil(|py| wake_all(py, &self.waiter_queue));
+    }
 }
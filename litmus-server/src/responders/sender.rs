@@ -1,15 +1,45 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use crossbeam::channel::{bounded, Receiver, Sender, TryRecvError, TrySendError};
 use crossbeam::queue::SegQueue;
-use pyo3::exceptions::PyBlockingIOError;
+use pyo3::exceptions::PyValueError;
 use pyo3::prelude::*;
 
-use super::{SenderPayload, WakerQueue};
+use super::{
+    disconnected_error,
+    wake_all,
+    DisconnectFlag,
+    SendAwaitable,
+    SenderPayload,
+    WakerQueue,
+};
+use crate::traits::BaseTransport;
+use crate::transport::Transport;
 
 const HEADER_SEPARATOR: &[u8] = ": ".as_bytes();
 const LINE_SEPARATOR: &[u8] = "\r\n".as_bytes();
 const SERVER_HEADER: &[u8] = "server: Pyre".as_bytes();
+const CLOSE_HEADER: &[u8] = "connection: close".as_bytes();
+const REQUEST_ID_HEADER: &str = "x-request-id";
+const CHUNKED_HEADER: &[u8] = "transfer-encoding: chunked".as_bytes();
+const LAST_CHUNK: &[u8] = "0\r\n\r\n".as_bytes();
+
+/// How the end of the response body is signalled to the client.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Framing {
+    /// The response has no body, anything sent is discarded.
+    Empty,
+
+    /// The body is as long as the `content-length` header says.
+    Length,
+
+    /// The body is sent in chunks ended by an empty chunk.
+    Chunked,
+
+    /// The body ends when the connection is closed.
+    UntilClose,
+}
 
 /// The callable class that handling communication back to the server protocol.
 #[pyclass]
@@ -22,92 +52,110 @@ pub struct DataSender {
     /// can be written to again.
     waiter_queue: WakerQueue,
 
-    /// If the response is using chunked encoding or not or not set.
-    chunked_encoding: Option<bool>,
+    /// How the body of the response is framed, set once the start of the
+    /// response has been sent.
+    framing: Framing,
+
+    /// If the response is to a `HEAD` request, which never has a body
+    /// whatever its headers say.
+    head: bool,
+
+    /// The keep alive timeout in seconds if the server allows the
+    /// connection to be kept open after this response, when `None` a
+    /// `connection: close` header is always sent.
+    keep_alive: Option<u64>,
 
-    /// If the client has defined a given content length of the body.
-    expected_content_length: usize,
+    /// The transport of the connection the response is written to.
+    transport: Transport,
+
+    /// Set once the client has disconnected.
+    disconnected: DisconnectFlag,
+
+    /// The ID of the request echoed in the response unless the
+    /// application sets its own.
+    request_id: Option<String>,
 }
 
 impl DataSender {
     /// Create a new handler with the given sender.
-    pub fn new(tx: Sender<SenderPayload>, waiter_queue: WakerQueue) -> Self {
-        let chunked_encoding = None; // We expect nothing yet.
-        let expected_content_length: usize = 0; // We expect nothing yet.
-
+    pub fn new(
+        tx: Sender<SenderPayload>,
+        waiter_queue: WakerQueue,
+        head: bool,
+        keep_alive: Option<u64>,
+        transport: Transport,
+        disconnected: DisconnectFlag,
+        request_id: Option<String>,
+    ) -> Self {
         Self {
             tx,
             waiter_queue,
-            chunked_encoding,
-            expected_content_length,
+            framing: Framing::Empty,
+            head,
+            keep_alive,
+            transport,
+            disconnected,
+            request_id,
         }
     }
-}
 
-#[pymethods]
-impl DataSender {
-    /// Sends the a chunk of the main body to the handler.
-    ///
-    /// This raises a `BlockingIoError` if the queue / buffer is full, the
-    /// invoker should wait till the queue / buffer is no longer full.
+    /// Sends the payload to the handler without blocking, returning the
+    /// payload back if the channel is full.
     ///
-    /// Args:
-    ///     more_body:
-    ///         A boolean to determine if the server should expect any more
-    ///         chunks of body being sent or if the request is regarded as
-    ///         being 'complete'.
-    ///
-    ///     body:
-    ///         A chunk of bytes to be written to the socket.
-    fn send_body(&self, more_body: bool, body: Vec<u8>) -> PyResult<()> {
-        if self.expected_content_length == 0 {
-            return Ok(());
+    /// Raises a `ConnectionResetError` if the client has disconnected.
+    pub(crate) fn try_send(
+        &self,
+        payload: SenderPayload,
+    ) -> PyResult<Option<SenderPayload>> {
+        if self.is_disconnected() {
+            return Err(disconnected_error());
         }
 
-        let payload = (more_body, true, body);
-        if let Err(e) = self.tx.try_send(payload) {
-            if let TrySendError::Full(_) = e {
-                return Err(PyBlockingIOError::new_err(()));
-            }
-
-            // The connection has been dropped, ignore.
-            return Ok(());
+        match self.tx.try_send(payload) {
+            Ok(()) => self.transport.resume_writing().map(|_| None),
+            Err(TrySendError::Full(payload)) => Ok(Some(payload)),
+            Err(TrySendError::Disconnected(_)) => Err(disconnected_error()),
         }
+    }
 
-        Ok(())
+    /// Ends the response without completing the body, closing the
+    /// connection once what has already been sent is written so the client
+    /// can tell the response was cut short.
+    pub(crate) fn abort(slf: PyRef<Self>) -> PyResult<SendAwaitable> {
+        let payload = slf.try_send((false, false, Vec::new()))?;
+        Ok(SendAwaitable::new(slf.into(), payload))
     }
 
-    /// Sends the start of the response body to the handler.
-    ///
-    /// This raises a `BlockingIoError` if the queue / buffer is full, the
-    /// invoker should wait till the queue / buffer is no longer full.
-    ///
-    /// This raises a `RuntimeError` if the channel receiver has been dropped.
-    ///
-    /// Args:
-    ///     more_body:
-    ///         A boolean to determine if the server should expect any more
-    ///         chunks of body being sent or if the request is regarded as
-    ///         being 'complete'.
+    /// Submits a given callback to the waiter queue.
     ///
-    ///     body:
-    ///         A chunk of bytes to be written to the socket.
-    fn send_start(
+    /// Any waiters in the queue when the handler takes a payload out of the
+    /// channel are taken out of the queue and invoked with no arguments,
+    /// any errors raised by a waker are ignored.
+    pub(crate) fn subscribe(&self, waker: PyObject) {
+        self.waiter_queue.push(waker);
+    }
+
+    /// Formats the status line and headers of the response into the start
+    /// block sent to the handler.
+    fn build_start(
         &mut self,
         status_code: u16,
         resp_headers: Vec<(&[u8], &[u8])>,
-    ) -> PyResult<()> {
-        let mut keep_alive = true;
+    ) -> PyResult<SenderPayload> {
+        let mut keep_alive = self.keep_alive.is_some();
+        let mut request_id = self.request_id.as_ref();
+        let mut content_length = None;
+        let mut chunked = false;
         let mut out = Vec::with_capacity(resp_headers.len() + 4);
 
         let status = match http::StatusCode::from_u16(status_code) {
             Ok(s) => s,
-            Err(_) => panic!("invalid status code given"),
+            Err(_) => return Err(PyValueError::new_err("invalid status code given")),
         };
         let status_block = format!(
             "HTTP/1.1 {} {}",
             status.as_str(),
-            status.canonical_reason().unwrap_or_else(|| ""),
+            status.canonical_reason().unwrap_or(""),
         )
         .as_bytes()
         .to_vec();
@@ -116,23 +164,31 @@ impl DataSender {
         for (name, value) in resp_headers {
             let name = match headers::HeaderName::from_bytes(name) {
                 Ok(s) => s,
-                Err(_) => panic!("invalid header name given"),
+                Err(_) => {
+                    return Err(PyValueError::new_err("invalid header name given"))
+                },
             };
 
             let value = match headers::HeaderValue::from_bytes(value) {
                 Ok(s) => s,
-                Err(_) => panic!("invalid status code given"),
+                Err(_) => {
+                    return Err(PyValueError::new_err("invalid header value given"))
+                },
             };
 
-            match &name {
-                &http::header::CONTENT_LENGTH => {
-                    self.expected_content_length = value
-                        .to_str()
-                        .expect("content length header is not ASCII encodable")
-                        .parse()
-                        .expect("content length header contains invalid integer")
+            match name {
+                http::header::CONTENT_LENGTH => {
+                    content_length =
+                        match value.to_str().ok().and_then(|v| v.parse::<usize>().ok()) {
+                            Some(length) => Some(length),
+                            None => {
+                                return Err(PyValueError::new_err(
+                                    "content length header contains invalid integer",
+                                ))
+                            },
+                        }
                 },
-                &http::header::TRANSFER_ENCODING => {
+                http::header::TRANSFER_ENCODING => {
                     let temp_val = value.as_ref();
                     if temp_val.len() == 7 {
                         // This compares each explicit character,
@@ -147,24 +203,32 @@ impl DataSender {
                         (temp_val[6] == 100)
                         // d
                         {
-                            self.chunked_encoding = Some(true);
+                            chunked = true;
                         }
                     };
                 },
-                &http::header::CONNECTION => {
+                http::header::CONNECTION => {
+                    if self.keep_alive.is_none() {
+                        // Replaced by the server's own header.
+                        continue;
+                    }
+
                     let temp_val = value.as_ref();
-                    if temp_val.len() == 5 {
-                        if (temp_val[0] == 99) &   // c
+                    if (temp_val.len() == 5)
+                        && (temp_val[0] == 99) &   // c
                         (temp_val[1] == 108) &  // l
                         (temp_val[2] == 111) &  // o
                         (temp_val[3] == 115) &  // s
                         (temp_val[4] == 101)
-                        // e
-                        {
-                            keep_alive = false;
-                        }
+                    // e
+                    {
+                        keep_alive = false;
                     }
                 },
+                _ if name == REQUEST_ID_HEADER => {
+                    // The application's own ID takes priority.
+                    request_id = None;
+                },
                 _ => {},
             }
 
@@ -173,6 +237,38 @@ impl DataSender {
             out.push(res);
         }
 
+        if let Some(request_id) = request_id {
+            out.push(format!("{}: {}", REQUEST_ID_HEADER, request_id).into_bytes());
+        }
+
+        let has_body = !(status.is_informational()
+            | (status == http::StatusCode::NO_CONTENT)
+            | (status == http::StatusCode::NOT_MODIFIED));
+        self.framing = match content_length {
+            // The headers describe the body a `GET` would have got, so the
+            // application's content length is kept but nothing is sent.
+            _ if !has_body | self.head => Framing::Empty,
+            Some(0) => Framing::Empty,
+            Some(_) => Framing::Length,
+            None if chunked => Framing::Chunked,
+            // Without a length the body is chunked if the connection is to
+            // be reused, otherwise closing the connection ends it which
+            // also works for HTTP/1.0 clients.
+            None if self.keep_alive.is_some() => {
+                out.push(CHUNKED_HEADER.to_vec());
+                Framing::Chunked
+            },
+            None => Framing::UntilClose,
+        };
+
+        match self.keep_alive {
+            Some(timeout) if keep_alive => {
+                out.push(format!("keep-alive: timeout={}", timeout).into_bytes())
+            },
+            Some(_) => {},
+            None => out.push(CLOSE_HEADER.to_vec()),
+        }
+
         let formatted_date_header = format!(
             "date: {}",
             httpdate::fmt_http_date(std::time::SystemTime::now()),
@@ -186,29 +282,100 @@ impl DataSender {
         // Joins all separate lines into a single block with \r\n joining them.
         let start_block = out.join(LINE_SEPARATOR);
 
-        return match self.tx.try_send((true, keep_alive, start_block)) {
-            Err(TrySendError::Full(_)) => Err(PyBlockingIOError::new_err(())),
-            _ => Ok(()),
-        };
+        Ok((true, keep_alive, start_block))
     }
+}
 
-    /// Submits a given callback to the waiter queue.
+#[pymethods]
+impl DataSender {
+    /// If the client has disconnected, once disconnected any further sends
+    /// raise a `ConnectionResetError`.
+    pub(crate) fn is_disconnected(&self) -> bool {
+        self.disconnected.load(Ordering::Relaxed)
+    }
+
+    /// Sends the a chunk of the main body to the handler.
     ///
-    /// Any waiters in the queue when the socket is able to be written to will
-    /// be taken out of the queue and invoked signalling the system's ability
-    /// to be written to again.
+    /// Args:
+    /// ```text
+    ///     more_body:
+    ///         A boolean to determine if the server should expect any more
+    ///         chunks of body being sent or if the request is regarded as
+    ///         being 'complete'.
+    ///
+    ///     body:
+    ///         A chunk of bytes to be written to the socket.
+    /// ```
+    ///
+    /// Returns:
+    /// ```text
+    ///     An awaitable which completes once the chunk has been handed to
+    ///     the server, waiting for the server to catch up if the buffer of
+    ///     pending chunks is full.
+    /// ```
     ///
-    /// All waker callbacks are invoked with no parameters or key word
-    /// arguments and are expected not to directly raise an error, in the case
-    /// that a waker does raise an error the exception is ignored and implicitly
-    /// silenced.
+    /// Raises:
+    /// ```text
+    ///     ConnectionResetError:
+    ///         If the client has disconnected.
+    /// ```
+    pub(crate) fn send_body(
+        slf: PyRef<Self>,
+        more_body: bool,
+        body: Vec<u8>,
+    ) -> PyResult<SendAwaitable> {
+        let body = match slf.framing {
+            // No body is expected but the end of the response still
+            // needs to reach the handler.
+            Framing::Empty if more_body => {
+                return Ok(SendAwaitable::new(slf.into(), None));
+            },
+            Framing::Empty => Vec::new(),
+            // An empty chunk would end the body early.
+            Framing::Chunked if more_body & body.is_empty() => {
+                return Ok(SendAwaitable::new(slf.into(), None));
+            },
+            Framing::Chunked => encode_chunk(body, more_body),
+            Framing::Length | Framing::UntilClose => body,
+        };
+
+        let payload = slf.try_send((more_body, true, body))?;
+        Ok(SendAwaitable::new(slf.into(), payload))
+    }
+
+    /// Sends the start of the response body to the handler.
     ///
     /// Args:
-    ///     waker:
-    ///         A callback to be invoked when data can be written to the socket
-    ///         without blocking.
-    fn subscribe(&self, waker: PyObject) {
-        self.waiter_queue.push(waker);
+    /// ```text
+    ///     status_code:
+    ///         The status code of the response.
+    ///
+    ///     resp_headers:
+    ///         A list of (name, value) header pairs.
+    /// ```
+    ///
+    /// Returns:
+    /// ```text
+    ///     An awaitable which completes once the start of the response has
+    ///     been handed to the server.
+    /// ```
+    ///
+    /// Raises:
+    /// ```text
+    ///     ValueError:
+    ///         If the status code or any of the headers are invalid.
+    ///
+    ///     ConnectionResetError:
+    ///         If the client has disconnected.
+    /// ```
+    pub(crate) fn send_start(
+        mut slf: PyRefMut<Self>,
+        status_code: u16,
+        resp_headers: Vec<(&[u8], &[u8])>,
+    ) -> PyResult<SendAwaitable> {
+        let payload = slf.build_start(status_code, resp_headers)?;
+        let payload = slf.try_send(payload)?;
+        Ok(SendAwaitable::new(slf.into(), payload))
     }
 }
 
@@ -222,6 +389,15 @@ pub struct SenderFactory {
     /// A queue of waiting events to invoke before the body
     /// can be written to again.
     waiter_queue: WakerQueue,
+
+    /// Set once the client has disconnected.
+    disconnected: DisconnectFlag,
+}
+
+impl Default for SenderFactory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SenderFactory {
@@ -234,12 +410,34 @@ impl SenderFactory {
             sender_tx: tx,
             sender_rx: rx,
             waiter_queue: queue,
+            disconnected: Arc::new(AtomicBool::new(false)),
         }
     }
 
     /// Makes a new sending handle with the given factory channels and queue.
-    pub fn make_handle(&self) -> DataSender {
-        DataSender::new(self.sender_tx.clone(), self.waiter_queue.clone())
+    ///
+    /// `method` is the method of the request being responded to, responses
+    /// to `HEAD` requests never send a body. `keep_alive` is the keep alive timeout advertised to the client, if
+    /// `None` the response produced by the handle will tell the client the
+    /// connection is closing. Sending data through the handle resumes
+    /// writing on the given transport. If given the `request_id` is sent
+    /// as a response header.
+    pub fn make_handle(
+        &self,
+        method: &str,
+        keep_alive: Option<u64>,
+        transport: Transport,
+        request_id: Option<String>,
+    ) -> DataSender {
+        DataSender::new(
+            self.sender_tx.clone(),
+            self.waiter_queue.clone(),
+            method == "HEAD",
+            keep_alive,
+            transport,
+            self.disconnected.clone(),
+            request_id,
+        )
     }
 
     /// Receives data from any DataSenders that have submitted
@@ -248,14 +446,39 @@ impl SenderFactory {
     /// This also implicitly wakes up any waiters waiting on a notifying them
     /// that they can send to the handler again.
     pub fn recv(&self) -> Result<SenderPayload, TryRecvError> {
-        if self.waiter_queue.len() > 0 {
-            Python::with_gil(|py| {
-                while let Some(waker) = self.waiter_queue.pop() {
-                    // The waker should not affect the reader
-                    let _ = waker.call0(py);
-                }
-            });
+        if !self.waiter_queue.is_empty() {
+            Python::with_gil(|py| wake_all(py, &self.waiter_queue));
         }
         self.sender_rx.try_recv()
     }
+
+    /// If nothing sent by the DataSenders is waiting to be received.
+    pub fn is_empty(&self) -> bool {
+        self.sender_rx.is_empty()
+    }
+
+    /// Marks the client as disconnected, waking any waiters so they can
+    /// observe the disconnect.
+    pub fn disconnect(&self) {
+        self.disconnected.store(true, Ordering::Relaxed);
+        Python::with_gil(|py| wake_all(py, &self.waiter_queue));
+    }
+}
+
+/// Frames a part of the body as a chunk, followed by the last chunk if
+/// there is no more body to come.
+fn encode_chunk(body: Vec<u8>, more_body: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len() + 16);
+    if !body.is_empty() {
+        out.extend_from_slice(format!("{:x}", body.len()).as_bytes());
+        out.extend_from_slice(LINE_SEPARATOR);
+        out.extend_from_slice(&body);
+        out.extend_from_slice(LINE_SEPARATOR);
+    }
+
+    if !more_body {
+        out.extend_from_slice(LAST_CHUNK);
+    }
+
+    out
 }
@@ -1,14 +1,25 @@
+use std::net::SocketAddr;
 use std::sync::Arc;
 
+use bytes::BytesMut;
+use http::StatusCode;
+use pyo3::exceptions::{PyConnectionRefusedError, PyValueError};
 use pyo3::prelude::*;
-use pyo3::types::PyTuple;
+use pyo3::types::{PyDict, PyTuple};
+use tracing::instrument;
 
 use crate::client::ClientHandler;
 use crate::event_loop::EventLoop;
+use crate::health::{Health, Probes, SharedHealth};
+use crate::lifespan::Lifespan;
 use crate::manager::ClientManager;
-use crate::net::{NoneBlockingListener, Status};
+use crate::metrics::{Metrics, MetricsListener, SharedMetrics};
+use crate::net::{NoneBlockingListener, Status, StreamHandle};
+use crate::protocols::server_response;
 use crate::settings::{ServerSettings, Settings};
+use crate::testing::TestConnection;
 use crate::traits::RawPollHandler;
+use crate::watchdog::Watchdog;
 
 /// A cheaply cloneable helper function that wraps a python callback.
 #[derive(Clone)]
@@ -24,12 +35,10 @@ impl CallbackHandler {
         Self { cb: Arc::new(cb) }
     }
 
-    /// Invokes the callback by acquiring the gil internally.
-    pub(crate) fn invoke(&self, args: impl IntoPy<Py<PyTuple>>) -> PyResult<()> {
-        Python::with_gil(|py| -> PyResult<()> {
-            let _ = self.cb.call1(py, args)?;
-            Ok(())
-        })
+    /// Invokes the callback by acquiring the gil internally, returning
+    /// whatever the callback returned.
+    pub(crate) fn invoke(&self, args: impl IntoPy<Py<PyTuple>>) -> PyResult<PyObject> {
+        Python::with_gil(|py| self.cb.call1(py, args))
     }
 }
 
@@ -44,28 +53,93 @@ pub struct Server {
     listeners: Vec<NoneBlockingListener>,
 
     manager: Option<ClientManager<ClientHandler>>,
+
+    /// The Python callback used to register a listener with the event loop.
+    accept_callback: Option<PyObject>,
+
+    /// If the listeners are currently registered with the event loop.
+    accepting: bool,
+
+    /// If the server is draining, once draining no new connections are
+    /// accepted.
+    draining: bool,
+
+    /// Runs the application's lifespan startup and shutdown.
+    lifespan: Lifespan,
+
+    /// The server's metrics.
+    metrics: SharedMetrics,
+
+    /// Serves the metrics in the Prometheus format if enabled.
+    metrics_listener: Option<MetricsListener>,
+
+    /// The server's health reported by the probe endpoints.
+    health: SharedHealth,
+
+    /// Warns when the event loop is blocked if enabled.
+    watchdog: Option<Watchdog>,
 }
 
 impl Server {
-    #[timed::timed(duration(printer = "trace!"))]
+    #[instrument(level = "trace", skip_all)]
     pub fn connect(
         settings: ServerSettings,
         callback: PyObject,
         binders: Vec<&str>,
+        proxy_binders: Vec<&str>,
     ) -> PyResult<Self> {
+        if let Some(bind) = proxy_binders.iter().find(|b| !binders.contains(b)) {
+            return Err(PyValueError::new_err(format!(
+                "PROXY protocol enabled for {} which is not being listened on",
+                bind
+            )));
+        }
+
         let mut listeners = Vec::new();
         for bind in binders {
-            info!("binding to {}", bind);
-            let listener = NoneBlockingListener::bind(bind)?;
+            let proxy_protocol = proxy_binders.contains(&bind);
+            info!("binding to {} (PROXY protocol: {})", bind, proxy_protocol);
+            let listener = NoneBlockingListener::bind(bind, proxy_protocol)?;
             listeners.push(listener);
         }
 
+        let metrics = SharedMetrics::default();
+        let health = Arc::new(Health::new());
+        let metrics_listener = match settings.metrics_bind.as_ref() {
+            Some(bind) => {
+                let probes = Probes {
+                    health: health.clone(),
+                    health_path: settings.health_path.clone(),
+                    ready_path: settings.ready_path.clone(),
+                    heartbeat_timeout: settings.heartbeat_timeout,
+                };
+
+                Some(MetricsListener::bind(bind, metrics.clone(), probes)?)
+            },
+            None => None,
+        };
+
+        let watchdog = match settings.stall_threshold {
+            Some(threshold) => {
+                Some(Watchdog::spawn(threshold, health.clone(), metrics.clone())?)
+            },
+            None => None,
+        };
+
         Ok(Self {
+            lifespan: Lifespan::new(settings.lifespan),
             settings: Arc::from(settings),
             callback: CallbackHandler::new(callback),
             listeners,
             event_loop: None,
             manager: None,
+            accept_callback: None,
+            accepting: false,
+            draining: false,
+            metrics,
+            metrics_listener,
+            health,
+            watchdog,
         })
     }
 
@@ -80,9 +154,113 @@ impl Server {
     }
 
     #[inline]
-    fn manager(&mut self) -> &mut ClientManager<ClientHandler> {
+    pub(crate) fn manager(&mut self) -> &mut ClientManager<ClientHandler> {
         self.manager.as_mut().expect("initialised")
     }
+
+    /// The client at the given index if it is still being handled.
+    pub(crate) fn client(&self, index: usize) -> Option<&ClientHandler> {
+        self.manager.as_ref()?.client(index)
+    }
+
+    /// The number of new connections that can be handled before the
+    /// connection limit is reached.
+    fn remaining_connections(&mut self) -> usize {
+        match self.settings.max_connections {
+            Some(max) => max.saturating_sub(self.manager().len_clients()),
+            None => usize::MAX,
+        }
+    }
+
+    /// Removes the listeners from the event loop so no new connections
+    /// are accepted until `resume_accepting` is called.
+    fn pause_accepting(&mut self) -> PyResult<()> {
+        if !self.accepting {
+            return Ok(());
+        }
+
+        for listener in self.listeners.iter() {
+            self.event_loop().remove_reader(listener.fd())?;
+        }
+        self.accepting = false;
+
+        Ok(())
+    }
+
+    /// Re-registers the listeners with the event loop if they were paused
+    /// and the server has room for new connections again.
+    fn resume_accepting(&mut self, py: Python) -> PyResult<()> {
+        if self.accepting | self.draining | (self.remaining_connections() == 0) {
+            return Ok(());
+        }
+
+        if let Some(cb) = self.accept_callback.as_ref() {
+            for (index, listener) in self.listeners.iter().enumerate() {
+                let _ = cb.call1(py, (listener.fd(), index))?;
+            }
+
+            info!("connection limit cleared, resuming all listeners");
+            self.accepting = true;
+        }
+
+        Ok(())
+    }
+}
+
+/// What became of a connection given to the server outside of a listener.
+pub(crate) enum Admission {
+    /// The connection is being handled at the given index.
+    Accepted(usize),
+
+    /// The server is full, the connection waits until there is room.
+    Waiting(StreamHandle),
+
+    /// The connection was turned away, with a `503 Service Unavailable` if
+    /// the server is full and rejecting overflow.
+    Closed,
+}
+
+impl Server {
+    /// Accepts an in-memory connection the same as `poll_accept` would a
+    /// connection from a listener.
+    pub(crate) fn accept_in_memory(
+        &mut self,
+        mut conn: StreamHandle,
+    ) -> PyResult<Admission> {
+        if self.draining {
+            conn.close();
+            return Ok(Admission::Closed);
+        }
+
+        if self.remaining_connections() > 0 {
+            let index = self.manager().handle_connection(conn)?;
+            return Ok(Admission::Accepted(index));
+        }
+
+        if self.settings.reject_overflow {
+            reject_connection(conn, &self.metrics);
+            return Ok(Admission::Closed);
+        }
+
+        Ok(Admission::Waiting(conn))
+    }
+}
+
+/// Answers a connection with a `503 Service Unavailable` without waiting for
+/// the socket to become writable and closes it.
+fn reject_connection(mut conn: StreamHandle, metrics: &Metrics) {
+    metrics.connection_rejected();
+
+    debug!(
+        client:% = conn.addr;
+        "rejecting connection from {:?}, server is full",
+        conn.addr
+    );
+
+    let mut buffer =
+        BytesMut::from(&server_response(StatusCode::SERVICE_UNAVAILABLE, "", false)[..]);
+    let _ = conn.write(&mut buffer);
+    conn.close();
 }
 
 #[pymethods]
@@ -104,9 +282,31 @@ impl Server {
             start.elapsed()
         );
 
+        self.accept_callback = Some(accept_callback);
+        self.accepting = true;
+
+        // The application has started by the time the server is ignited.
+        self.health.heartbeat();
+        self.health.set_ready(true);
+
         Ok(())
     }
 
+    /// Starts the application's lifespan scope, returning an awaitable
+    /// which completes once the application has started.
+    ///
+    /// This should be awaited before calling `ignite`, the awaitable raises
+    /// a `RuntimeError` if the application fails to start.
+    fn lifespan_startup(&mut self, py: Python) -> PyResult<PyObject> {
+        self.lifespan.startup(py, &self.callback)
+    }
+
+    /// Shuts down the application's lifespan scope, returning an awaitable
+    /// which completes once the application has shut down.
+    fn lifespan_shutdown(&mut self, py: Python) -> PyResult<PyObject> {
+        self.lifespan.shutdown(py)
+    }
+
     fn init(
         &mut self,
         add_reader: PyObject,
@@ -129,6 +329,8 @@ impl Server {
             self.callback.clone(),
             self.event_loop().clone(),
             self.settings.clone(),
+            self.metrics.clone(),
+            self.health.clone(),
         ));
     }
 
@@ -136,17 +338,62 @@ impl Server {
         self.manager().len_clients()
     }
 
-    #[timed::timed(duration(printer = "trace!"))]
+    /// A snapshot of the server's metrics.
+    fn metrics<'a>(&self, py: Python<'a>) -> PyResult<&'a PyDict> {
+        self.metrics.to_dict(py)
+    }
+
+    /// Opens an in-memory connection to the application as if `client`
+    /// had connected to `server`, handled the same as a connection from a
+    /// listener.
+    ///
+    /// `wake` is called whenever the event loop would start polling the
+    /// connection's socket, the connection should then be pumped soon after.
+    fn test_connection(
+        slf: &PyCell<Self>,
+        client: &str,
+        server: &str,
+        tls: bool,
+        wake: PyObject,
+    ) -> PyResult<TestConnection> {
+        let parse = |addr: &str| {
+            addr.parse::<SocketAddr>().map_err(|e| {
+                PyValueError::new_err(format!("invalid address {:?}: {}", addr, e))
+            })
+        };
+
+        if slf.try_borrow()?.draining {
+            return Err(PyConnectionRefusedError::new_err(
+                "the server is no longer accepting connections",
+            ));
+        }
+
+        TestConnection::connect(slf, parse(client)?, parse(server)?, tls, wake)
+    }
+
+    #[instrument(level = "trace", skip(self))]
     fn poll_accept(&mut self, index: usize) -> PyResult<()> {
+        let mut remaining = self.remaining_connections();
+        let reject_overflow = self.settings.reject_overflow;
         let listener = &self.listeners[index];
 
         let mut accepted = Vec::new();
         let backlog = self.settings.backlog;
         for _ in 0..backlog {
+            if (remaining == 0) & !reject_overflow {
+                break;
+            }
+
             let maybe_handle = listener.accept()?;
 
             match maybe_handle {
-                Status::Successful(conn) => accepted.push(conn),
+                Status::Successful(conn) if remaining == 0 => {
+                    reject_connection(conn, &self.metrics)
+                },
+                Status::Successful(conn) => {
+                    remaining -= 1;
+                    accepted.push(conn);
+                },
                 Status::ShouldPause => break,
             }
         }
@@ -156,29 +403,54 @@ impl Server {
             manager.handle_connection(conn)?;
         }
 
+        if (remaining == 0) & !reject_overflow & self.accepting {
+            warn!("connection limit reached, pausing all listeners");
+            self.pause_accepting()?;
+        }
+
         Ok(())
     }
 
-    #[timed::timed(duration(printer = "trace!"))]
+    #[instrument(level = "trace", skip(self))]
     fn poll_read(&mut self, index: usize) -> PyResult<()> {
         self.manager().poll_read(index)
     }
 
-    #[timed::timed(duration(printer = "trace!"))]
+    #[instrument(level = "trace", skip(self))]
     fn poll_write(&mut self, index: usize) -> PyResult<()> {
         self.manager().poll_write(index)
     }
 
-    #[timed::timed(duration(printer = "trace!"))]
+    #[instrument(level = "trace", skip(self))]
     fn poll_close(&mut self, index: usize) -> PyResult<()> {
         self.manager().poll_close(index)
     }
 
-    fn poll_keep_alive(&mut self) -> PyResult<()> {
-        self.manager().poll_keep_alive()
+    fn poll_keep_alive(&mut self, py: Python) -> PyResult<()> {
+        // Called by the event loop on a timer so doubles as its heartbeat.
+        self.health.heartbeat();
+        self.manager().poll_keep_alive()?;
+        self.resume_accepting(py)
+    }
+
+    /// Stops accepting new connections and closes the listeners, returning
+    /// the tasks of the requests still being handled so they can be waited
+    /// on before shutting down.
+    fn drain(&mut self, py: Python) -> PyResult<Vec<PyObject>> {
+        if !self.draining {
+            info!("draining, no longer accepting new connections");
+            self.draining = true;
+            self.health.set_ready(false);
+            self.pause_accepting()?;
+            self.listeners.clear();
+        }
+
+        Ok(self.manager().pending_tasks(py))
     }
 
     fn shutdown(&mut self) -> PyResult<()> {
+        self.metrics_listener = None;
+        self.watchdog = None;
         self.manager().shutdown()
     }
 }
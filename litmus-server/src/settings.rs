@@ -1,9 +1,32 @@
 use std::sync::Arc;
 use std::time::Duration;
 
+pub use crate::access_log::{AccessLogFormat, ACCESS_LOG_TARGET};
+pub use crate::forwarded::IpNetwork;
+pub use crate::lifespan::LifespanMode;
+
 pub type Settings = Arc<ServerSettings>;
 
 pub struct ServerSettings {
     pub backlog: usize,
     pub keep_alive: Duration,
+    pub header_timeout: Duration,
+    pub body_timeout: Duration,
+    pub write_timeout: Duration,
+    pub max_connections: Option<usize>,
+    pub reject_overflow: bool,
+    pub max_requests_per_connection: Option<usize>,
+    pub root_path: String,
+    pub forwarded_prefix: bool,
+    pub trusted_proxies: Vec<IpNetwork>,
+    pub cancel_on_disconnect: bool,
+    pub cancel_grace_period: Duration,
+    pub lifespan: LifespanMode,
+    pub access_log_format: AccessLogFormat,
+    pub metrics_bind: Option<String>,
+    pub echo_request_id: bool,
+    pub health_path: Option<String>,
+    pub ready_path: Option<String>,
+    pub heartbeat_timeout: Duration,
+    pub stall_threshold: Option<Duration>,
 }
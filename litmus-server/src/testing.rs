@@ -0,0 +1,336 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use bytes::{BufMut, BytesMut};
+use pyo3::exceptions::PyConnectionError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::net::{SocketStatus, StreamHandle};
+use crate::server::{Admission, Server};
+use crate::traits::{BaseTransport, PollHandler, RawPollHandler};
+
+/// How much the server can write before the client has to receive it,
+/// standing in for the socket's send buffer.
+const SEND_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The state of an in-memory socket shared between the server's handle
+/// to it and the test connection.
+#[derive(Default)]
+struct MemoryState {
+    /// Data sent by the client that the server has not yet read.
+    inbound: BytesMut,
+
+    /// Data written by the server that the client has not yet received.
+    outbound: BytesMut,
+
+    /// If the client has closed its side of the connection.
+    eof: bool,
+
+    /// If the server has read the end of file sent by the client.
+    eof_read: bool,
+
+    /// If the server has shut the socket down.
+    shut: bool,
+
+    /// If the event loop would be polling the socket for reads.
+    reading: bool,
+
+    /// If the event loop would be polling the socket for writes.
+    writing: bool,
+
+    /// If the transport has asked for the socket to be closed.
+    close_requested: bool,
+}
+
+/// A socket held in memory, reading from and writing to byte buffers the
+/// test connection fills and drains.
+///
+/// It stands in for both the socket and the event loop's interest in it,
+/// `wake` is invoked whenever the event loop would start polling the
+/// socket so the test connection can poll it in turn.
+#[derive(Clone)]
+pub(crate) struct MemorySocket {
+    state: Arc<Mutex<MemoryState>>,
+    wake: Arc<PyObject>,
+}
+
+impl MemorySocket {
+    fn new(wake: PyObject) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MemoryState::default())),
+            wake: Arc::new(wake),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, MemoryState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn wake(&self) -> PyResult<()> {
+        Python::with_gil(|py| self.wake.call0(py))?;
+        Ok(())
+    }
+
+    /// Reads as much of the data sent by the client as fits in the buffer.
+    pub(crate) fn read(&self, buffer: &mut BytesMut) -> SocketStatus {
+        let mut state = self.state();
+        if state.inbound.is_empty() {
+            if !state.eof {
+                return SocketStatus::WouldBlock;
+            }
+
+            state.eof_read = true;
+            return SocketStatus::Complete(0);
+        }
+
+        let len = buffer.chunk_mut().len().min(state.inbound.len());
+        buffer.extend_from_slice(&state.inbound.split_to(len));
+
+        SocketStatus::Complete(len)
+    }
+
+    /// Writes as much of the buffer as the client has room to receive.
+    pub(crate) fn write(&self, buffer: &mut BytesMut) -> SocketStatus {
+        let mut state = self.state();
+        if state.shut | state.eof {
+            return SocketStatus::Disconnect;
+        }
+
+        let len = SEND_BUFFER_SIZE
+            .saturating_sub(state.outbound.len())
+            .min(buffer.len());
+        if (len == 0) & !buffer.is_empty() {
+            return SocketStatus::WouldBlock;
+        }
+
+        state.outbound.extend_from_slice(&buffer.split_to(len));
+
+        SocketStatus::Complete(len)
+    }
+
+    /// Shuts the socket down, the client sees the connection as closed.
+    pub(crate) fn shutdown(&self) {
+        self.state().shut = true;
+    }
+}
+
+impl BaseTransport for MemorySocket {
+    /// Asks for the socket to be closed the next time it is polled, the
+    /// same as the event loop closing it with `call_soon`.
+    fn close(&self) -> PyResult<()> {
+        self.state().close_requested = true;
+        self.wake()
+    }
+
+    fn pause_reading(&self) -> PyResult<()> {
+        self.state().reading = false;
+        Ok(())
+    }
+
+    fn resume_reading(&self) -> PyResult<()> {
+        self.state().reading = true;
+        self.wake()
+    }
+
+    fn pause_writing(&self) -> PyResult<()> {
+        self.state().writing = false;
+        Ok(())
+    }
+
+    fn resume_writing(&self) -> PyResult<()> {
+        self.state().writing = true;
+        self.wake()
+    }
+}
+
+/// What the event loop would do next for a socket.
+enum Readiness {
+    Read,
+    Write,
+    Close,
+    Nothing,
+}
+
+/// A connection to the server held entirely in memory, raw request bytes
+/// are fed in with `send` and the exact response bytes read back with
+/// `receive`.
+///
+/// The connection is accepted by the server and handled the same as one
+/// from a listener, so the connection limit, keep alive and request
+/// timeouts all apply to it.
+#[pyclass(name = "_TestConnection")]
+pub struct TestConnection {
+    server: Py<Server>,
+    socket: MemorySocket,
+
+    /// The connection while it waits for the server to have room for it.
+    waiting: Option<StreamHandle>,
+
+    /// The index of the connection in the client manager once accepted.
+    index: Option<usize>,
+}
+
+impl TestConnection {
+    pub(crate) fn connect(
+        server: &PyCell<Server>,
+        client: SocketAddr,
+        server_addr: SocketAddr,
+        tls: bool,
+        wake: PyObject,
+    ) -> PyResult<Self> {
+        let socket = MemorySocket::new(wake);
+        let conn = StreamHandle::in_memory(socket.clone(), client, server_addr, tls);
+
+        let mut test_conn = Self {
+            server: server.into(),
+            socket,
+            waiting: None,
+            index: None,
+        };
+        test_conn.admit(&mut *server.try_borrow_mut()?, conn)?;
+
+        Ok(test_conn)
+    }
+
+    fn admit(&mut self, server: &mut Server, conn: StreamHandle) -> PyResult<()> {
+        match server.accept_in_memory(conn)? {
+            Admission::Accepted(index) => self.index = Some(index),
+            Admission::Waiting(conn) => self.waiting = Some(conn),
+            Admission::Closed => {},
+        }
+
+        Ok(())
+    }
+
+    fn readiness(&self) -> Readiness {
+        let state = self.socket.state();
+        if state.shut {
+            Readiness::Nothing
+        } else if state.reading
+            & (!state.inbound.is_empty() | (state.eof & !state.eof_read))
+        {
+            Readiness::Read
+        } else if state.writing & (state.outbound.len() < SEND_BUFFER_SIZE) {
+            Readiness::Write
+        } else if state.close_requested {
+            Readiness::Close
+        } else {
+            Readiness::Nothing
+        }
+    }
+
+    /// If the server has released the connection, its index may since
+    /// have been given to another one.
+    fn is_released(&self) -> bool {
+        self.socket.state().shut
+    }
+}
+
+#[pymethods]
+impl TestConnection {
+    /// Polls the connection the same as the event loop would, until the
+    /// server has nothing left it can read or write.
+    fn pump(&mut self, py: Python) -> PyResult<()> {
+        let server = self.server.clone_ref(py);
+        let mut server = server.as_ref(py).try_borrow_mut()?;
+
+        if let Some(conn) = self.waiting.take() {
+            self.admit(&mut server, conn)?;
+        }
+
+        let index = match self.index {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+
+        let manager = server.manager();
+        loop {
+            match self.readiness() {
+                Readiness::Read => manager.poll_read(index)?,
+                Readiness::Write => manager.poll_write(index)?,
+                Readiness::Close => {
+                    self.socket.state().close_requested = false;
+                    manager.poll_close(index)?;
+                },
+                Readiness::Nothing => return Ok(()),
+            }
+        }
+    }
+
+    /// Sends raw bytes to the server as the client.
+    fn send(&mut self, py: Python, data: &[u8]) -> PyResult<()> {
+        {
+            let mut state = self.socket.state();
+            if state.shut | state.eof {
+                return Err(PyConnectionError::new_err(
+                    "the connection has been closed",
+                ));
+            }
+
+            state.inbound.extend_from_slice(data);
+        }
+
+        self.pump(py)
+    }
+
+    /// Returns all the bytes the server has written since the last call.
+    fn receive(&mut self, py: Python) -> PyResult<Py<PyBytes>> {
+        self.pump(py)?;
+
+        let out = self.socket.state().outbound.split();
+
+        // Receiving makes room for anything the server was holding back,
+        // which it writes the next time the connection is polled.
+        if !out.is_empty() {
+            self.socket.wake()?;
+        }
+
+        Ok(PyBytes::new(py, &out).into())
+    }
+
+    /// Closes the connection from the client's side.
+    fn close(&mut self, py: Python) -> PyResult<()> {
+        self.socket.state().eof = true;
+        self.waiting = None;
+        self.pump(py)
+    }
+
+    /// If the connection has been closed by either side.
+    #[getter]
+    fn closed(&self) -> bool {
+        let state = self.socket.state();
+        state.shut | state.eof
+    }
+
+    /// If the connection is waiting for a new request with everything
+    /// written received by the client.
+    #[getter]
+    fn idle(&self, py: Python) -> PyResult<bool> {
+        let index = match self.index {
+            Some(index) if !self.is_released() => index,
+            _ => return Ok(false),
+        };
+
+        let server = self.server.as_ref(py).try_borrow()?;
+        let awaiting = server
+            .client(index)
+            .is_some_and(|client| client.is_awaiting_request());
+
+        Ok(awaiting & self.socket.state().outbound.is_empty())
+    }
+
+    /// The task handling the current request if it is still running.
+    #[getter]
+    fn pending_task(&self, py: Python) -> PyResult<Option<PyObject>> {
+        let index = match self.index {
+            Some(index) if !self.is_released() => index,
+            _ => return Ok(None),
+        };
+
+        let server = self.server.as_ref(py).try_borrow()?;
+        Ok(server
+            .client(index)
+            .and_then(|client| client.pending_task(py)))
+    }
+}
@@ -1,7 +1,9 @@
 use bytes::BytesMut;
-use pyo3::PyResult;
+use pyo3::{PyObject, PyResult, Python};
 
 use crate::event_loop::PreSetEventLoop;
+use crate::health::SharedHealth;
+use crate::metrics::SharedMetrics;
 use crate::net::StreamHandle;
 use crate::server::CallbackHandler;
 use crate::settings::Settings;
@@ -13,9 +15,9 @@ pub(crate) trait Reusable: Sized {
         event_loop: PreSetEventLoop,
         conn: StreamHandle,
         settings: Settings,
+        metrics: SharedMetrics,
+        health: SharedHealth,
     ) -> PyResult<Self>;
-
-    fn set_connection(&mut self, conn: StreamHandle) -> PyResult<()>;
 }
 
 pub trait PollHandler {
@@ -27,6 +29,7 @@ pub trait PollHandler {
     fn is_idle(&self) -> bool;
     fn is_free(&self) -> bool;
     fn set_free(&mut self);
+    fn pending_task(&self, py: Python) -> Option<PyObject>;
 }
 
 pub trait RawPollHandler {
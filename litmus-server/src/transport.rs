@@ -3,14 +3,26 @@ use std::net::SocketAddr;
 use pyo3::PyResult;
 
 use crate::event_loop::PreSetEventLoop;
+use crate::testing::MemorySocket;
 use crate::traits::BaseTransport;
 
+/// What the transport invokes to control the connection.
+#[derive(Clone)]
+enum Backend {
+    /// A socket polled by the Python event loop.
+    EventLoop(PreSetEventLoop),
+
+    /// An in-memory connection driven by the test client, at the given
+    /// index in the client manager.
+    Memory(MemorySocket, usize),
+}
+
 #[derive(Clone)]
 pub struct Transport {
     pub client: SocketAddr,
     pub server: SocketAddr,
     pub tls: bool,
-    event_loop: PreSetEventLoop,
+    backend: Backend,
 }
 
 impl Transport {
@@ -25,7 +37,32 @@ impl Transport {
             client,
             server,
             tls,
-            event_loop,
+            backend: Backend::EventLoop(event_loop),
+        }
+    }
+
+    /// Create a new transport instance for an in-memory connection at the
+    /// given index in the client manager.
+    pub(crate) fn in_memory(
+        client: SocketAddr,
+        server: SocketAddr,
+        tls: bool,
+        socket: MemorySocket,
+        index: usize,
+    ) -> Self {
+        Self {
+            client,
+            server,
+            tls,
+            backend: Backend::Memory(socket, index),
+        }
+    }
+
+    /// The index of the connection in the client manager.
+    pub fn index(&self) -> usize {
+        match &self.backend {
+            Backend::EventLoop(event_loop) => event_loop.index(),
+            Backend::Memory(_, index) => *index,
         }
     }
 }
@@ -36,30 +73,45 @@ impl BaseTransport for Transport {
     /// The closing itself is invoked using loop.call_soon, this is not
     /// guaranteed to be instant.
     fn close(&self) -> PyResult<()> {
-        self.event_loop.close_socket()
+        match &self.backend {
+            Backend::EventLoop(event_loop) => event_loop.close_socket(),
+            Backend::Memory(socket, _) => socket.close(),
+        }
     }
 
     /// Removes the file descriptor listener from the event loop
     /// therefore pausing reading callbacks.
     fn pause_reading(&self) -> PyResult<()> {
-        self.event_loop.remove_reader()
+        match &self.backend {
+            Backend::EventLoop(event_loop) => event_loop.remove_reader(),
+            Backend::Memory(socket, _) => socket.pause_reading(),
+        }
     }
 
     /// Adds the file descriptor listener to the event loop ready to start
     /// polling the reading callback when data can be read from the socket.
     fn resume_reading(&self) -> PyResult<()> {
-        self.event_loop.add_reader()
+        match &self.backend {
+            Backend::EventLoop(event_loop) => event_loop.add_reader(),
+            Backend::Memory(socket, _) => socket.resume_reading(),
+        }
     }
 
     /// Removes the file descriptor listener from the event loop
     /// therefore pausing writing callbacks.
     fn pause_writing(&self) -> PyResult<()> {
-        self.event_loop.remove_writer()
+        match &self.backend {
+            Backend::EventLoop(event_loop) => event_loop.remove_writer(),
+            Backend::Memory(socket, _) => socket.pause_writing(),
+        }
     }
 
     /// Adds the file descriptor listener to the event loop ready to start
     /// polling the writing callback when data can be written to the socket.
     fn resume_writing(&self) -> PyResult<()> {
-        self.event_loop.add_writer()
+        match &self.backend {
+            Backend::EventLoop(event_loop) => event_loop.add_writer(),
+            Backend::Memory(socket, _) => socket.resume_writing(),
+        }
     }
 }
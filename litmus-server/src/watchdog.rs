@@ -0,0 +1,86 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::health::SharedHealth;
+use crate::metrics::SharedMetrics;
+
+/// The most often the watchdog checks the event loop's heartbeat.
+const MIN_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The longest the watchdog waits between checks.
+const MAX_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Watches the event loop's heartbeat from a separate thread, warning with
+/// the requests in flight when the loop is blocked for longer than the
+/// threshold.
+///
+/// The thread stops once this is dropped.
+pub(crate) struct Watchdog {
+    stop: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    pub(crate) fn spawn(
+        threshold: Duration,
+        health: SharedHealth,
+        metrics: SharedMetrics,
+    ) -> std::io::Result<Self> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let interval = (threshold / 4).clamp(MIN_CHECK_INTERVAL, MAX_CHECK_INTERVAL);
+
+        let stopped = stop.clone();
+        thread::Builder::new()
+            .name("litmus-watchdog".to_string())
+            .spawn(move || {
+                let mut stalled = false;
+                while !stopped.load(Ordering::Relaxed) {
+                    thread::sleep(interval);
+
+                    let since = match health.since_heartbeat() {
+                        Some(since) => since,
+                        None => continue,
+                    };
+
+                    if !stalled & (since > threshold) {
+                        stalled = true;
+                        metrics.event_loop_stalled();
+                        report_stall(since, &health);
+                    } else if stalled & (since <= threshold) {
+                        stalled = false;
+                        warn!("event loop resumed after being blocked");
+                    }
+                }
+            })?;
+
+        Ok(Self { stop })
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Logs that the event loop is blocked along with every request that could
+/// be blocking it.
+fn report_stall(since: Duration, health: &SharedHealth) {
+    let in_flight = health.describe_in_flight();
+    if in_flight.is_empty() {
+        warn!(
+            stalled_ms = since.as_millis() as u64;
+            "event loop has been blocked for {:?} with no requests in flight",
+            since
+        );
+        return;
+    }
+
+    warn!(
+        stalled_ms = since.as_millis() as u64;
+        "event loop has been blocked for {:?}, requests in flight:\n  {}",
+        since,
+        in_flight.join("\n  ")
+    );
+}
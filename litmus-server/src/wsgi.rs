@@ -0,0 +1,806 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crossbeam::channel::{unbounded, Receiver, Sender, TryRecvError};
+use pyo3::class::iter::IterNextOutput;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use pyo3::{PyAsyncProtocol, PyIterProtocol, PyNativeType};
+
+use crate::lifespan::CompletedAwaitable;
+use crate::responders::{
+    advance,
+    create_waiter,
+    disconnected_error,
+    Awaitable,
+    DataReceiver,
+    DataSender,
+    Poll,
+    ReceiveAwaitable,
+    ReceiverPayload,
+    SendAwaitable,
+};
+
+/// The WSGI version given to applications in `wsgi.version`.
+const WSGI_VERSION: (u8, u8) = (1, 0);
+
+type Headers = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// A job run by the thread pool.
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed size pool of threads running the WSGI application.
+struct ThreadPool {
+    jobs: Sender<Job>,
+}
+
+impl ThreadPool {
+    /// Spawns the given number of threads, the threads exit once the pool
+    /// is dropped and the queued jobs have been run.
+    fn new(threads: usize) -> PyResult<Self> {
+        let (tx, rx) = unbounded::<Job>();
+
+        for i in 0..threads {
+            let rx = rx.clone();
+            thread::Builder::new()
+                .name(format!("litmus-wsgi-{}", i))
+                .spawn(move || {
+                    while let Ok(job) = rx.recv() {
+                        job();
+                    }
+                })?;
+        }
+
+        Ok(Self { jobs: tx })
+    }
+
+    fn submit(&self, job: Job) -> PyResult<()> {
+        self.jobs
+            .send(job)
+            .map_err(|_| PyRuntimeError::new_err("the WSGI thread pool has shut down"))
+    }
+}
+
+/// Adapts the LSGI (Litmus Server Gateway Interface) callback to a WSGI
+/// application run on a pool of threads.
+///
+/// The request body is streamed to `wsgi.input` and the response is
+/// streamed back to the client as the application produces it, the
+/// event loop is never blocked by the application.
+///
+/// WSGI applications have no lifespan, lifespan scopes complete without
+/// ever answering so the server treats them as unsupported.
+///
+/// Args:
+/// ```text
+///     app:
+///         The WSGI application, any callable taking
+///         `(environ, start_response)` and returning an iterable of bytes.
+///
+///     threads:
+///         The number of threads running the application, this is the
+///         number of requests that can be handled at once.
+/// ```
+#[pyclass]
+pub struct WSGIAdapter {
+    /// The WSGI application.
+    app: PyObject,
+
+    /// The threads the application is run on.
+    pool: ThreadPool,
+}
+
+#[pymethods]
+impl WSGIAdapter {
+    #[new]
+    #[args(threads = "8")]
+    fn new(app: PyObject, threads: usize) -> PyResult<Self> {
+        if threads == 0 {
+            return Err(PyValueError::new_err("threads must be at least 1"));
+        }
+
+        Ok(Self {
+            app,
+            pool: ThreadPool::new(threads)?,
+        })
+    }
+
+    /// Hands the request to the thread pool.
+    ///
+    /// Returns:
+    ///     An awaitable which completes once the application has finished
+    ///     responding.
+    #[call]
+    fn __call__(
+        &self,
+        py: Python,
+        scope: &PyDict,
+        send: PyObject,
+        receive: PyObject,
+    ) -> PyResult<PyObject> {
+        let scope_type: Option<&str> = match scope.get_item("type") {
+            Some(t) => Some(t.extract()?),
+            None => None,
+        };
+
+        if scope_type == Some("lifespan") {
+            return Ok(Py::new(py, CompletedAwaitable)?.into_py(py));
+        }
+
+        let (commands_tx, commands_rx) = unbounded();
+        let (replies_tx, replies_rx) = unbounded();
+        let waker = Arc::new(Mutex::new(None));
+
+        let bridge = Arc::new(Bridge {
+            commands: commands_tx,
+            replies: replies_rx,
+            waker: waker.clone(),
+            event_loop: py
+                .import("asyncio")?
+                .call_method0("get_running_loop")?
+                .into(),
+            response: Mutex::new(ResponseState::default()),
+        });
+
+        let input = Py::new(
+            py,
+            WSGIInput {
+                bridge: bridge.clone(),
+                buffer: Vec::new(),
+                eof: false,
+            },
+        )?;
+        let environ: PyObject = build_environ(py, scope, input.into_py(py))?.into();
+
+        let app = self.app.clone_ref(py);
+        self.pool
+            .submit(Box::new(move || run_app(app, environ, bridge)))?;
+
+        let awaitable = WSGIAwaitable {
+            sender: send.extract(py)?,
+            receiver: receive.extract(py)?,
+            commands: commands_rx,
+            replies: replies_tx,
+            waker,
+            pending: None,
+            queued: None,
+            waiter: None,
+            response_started: false,
+            finished: false,
+        };
+
+        Ok(Py::new(py, awaitable)?.into_py(py))
+    }
+}
+
+/// Decodes bytes as latin-1 like WSGI expects of the native strings in the
+/// environ.
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| *b as char).collect()
+}
+
+/// Builds the WSGI environ from the LSGI scope.
+fn build_environ<'a>(
+    py: Python<'a>,
+    scope: &'a PyDict,
+    input: PyObject,
+) -> PyResult<&'a PyDict> {
+    let get = |key: &str| {
+        scope
+            .get_item(key)
+            .ok_or_else(|| PyValueError::new_err(format!("scope is missing {:?}", key)))
+    };
+
+    let environ = PyDict::new(py);
+
+    let method: &str = get("method")?.extract()?;
+    let path: &str = get("path")?.extract()?;
+    let root_path: &str = get("root_path")?.extract()?;
+    let query_string: &[u8] = get("query_string")?.extract()?;
+    let http_version: &str = get("http_version")?.extract()?;
+    let scheme: &str = get("scheme")?.extract()?;
+    let (server_host, server_port): (String, u16) = get("server")?.extract()?;
+    let (client_host, client_port): (String, u16) = get("client")?.extract()?;
+
+    environ.set_item("REQUEST_METHOD", method)?;
+    environ.set_item("SCRIPT_NAME", latin1(root_path.as_bytes()))?;
+    environ.set_item("PATH_INFO", latin1(path.as_bytes()))?;
+    environ.set_item("QUERY_STRING", latin1(query_string))?;
+    environ.set_item("SERVER_NAME", server_host)?;
+    environ.set_item("SERVER_PORT", server_port.to_string())?;
+    environ.set_item("SERVER_PROTOCOL", format!("HTTP/{}", http_version))?;
+    environ.set_item("REMOTE_ADDR", client_host)?;
+    environ.set_item("REMOTE_PORT", client_port.to_string())?;
+
+    for pair in get("headers")?.iter()? {
+        let (name, value): (&[u8], &[u8]) = pair?.extract()?;
+
+        let key = match name {
+            b"content-type" => "CONTENT_TYPE".to_string(),
+            b"content-length" => "CONTENT_LENGTH".to_string(),
+            _ => format!("HTTP_{}", latin1(name).to_uppercase().replace('-', "_")),
+        };
+
+        let value = latin1(value);
+        let value = match environ.get_item(key.as_str()) {
+            Some(existing) => format!("{},{}", existing.extract::<&str>()?, value),
+            None => value,
+        };
+
+        environ.set_item(key, value)?;
+    }
+
+    environ.set_item("wsgi.version", WSGI_VERSION)?;
+    environ.set_item("wsgi.url_scheme", scheme)?;
+    environ.set_item("wsgi.input", input)?;
+    environ.set_item("wsgi.input_terminated", true)?;
+    environ.set_item("wsgi.errors", py.import("sys")?.getattr("stderr")?)?;
+    environ.set_item("wsgi.multithread", true)?;
+    environ.set_item("wsgi.multiprocess", false)?;
+    environ.set_item("wsgi.run_once", false)?;
+
+    if let Some(request_id) = scope.get_item("request_id") {
+        environ.set_item("litmus.request_id", request_id)?;
+    }
+
+    Ok(environ)
+}
+
+/// Runs the application on a pool thread, reporting the outcome to the
+/// awaitable on the event loop.
+fn run_app(app: PyObject, environ: PyObject, bridge: Arc<Bridge>) {
+    Python::with_gil(|py| {
+        let result = call_app(py, &app, environ, &bridge);
+
+        // The awaitable may have been dropped if the request was cancelled.
+        let _ = match result {
+            Ok(()) => bridge.finish(py),
+            Err(e) => bridge.notify(py, Command::Failed(e)),
+        };
+    })
+}
+
+/// Calls the application and writes each chunk of the iterable it returns,
+/// closing the iterable once done.
+fn call_app(
+    py: Python,
+    app: &PyObject,
+    environ: PyObject,
+    bridge: &Arc<Bridge>,
+) -> PyResult<()> {
+    let start_response = StartResponse {
+        bridge: bridge.clone(),
+    };
+    let iterable = app.call1(py, (environ, start_response))?;
+
+    let result = (|| {
+        for chunk in iterable.as_ref(py).iter()? {
+            let chunk = chunk?;
+            let chunk: &PyBytes = chunk.downcast()?;
+            bridge.write(py, chunk.as_bytes().to_vec())?;
+        }
+
+        Ok(())
+    })();
+
+    let iterable = iterable.as_ref(py);
+    if iterable.hasattr("close")? {
+        iterable.call_method0("close")?;
+    }
+
+    result
+}
+
+/// A request made by the application thread to the awaitable on the
+/// event loop.
+enum Command {
+    /// Receive the next chunk of the request body.
+    Read,
+
+    /// Send the status and headers of the response.
+    Start(u16, Headers),
+
+    /// Send a chunk of the response body.
+    Body(Vec<u8>),
+
+    /// The application has finished responding.
+    Finish,
+
+    /// The application raised an error.
+    Failed(PyErr),
+}
+
+/// The answer to a command from the awaitable.
+enum Reply {
+    /// A chunk of the request body.
+    Chunk(ReceiverPayload),
+
+    /// The status, headers or body chunk has been sent.
+    Sent,
+
+    /// The command failed.
+    Error(PyErr),
+}
+
+/// The status and headers given to `start_response`.
+#[derive(Default)]
+struct ResponseState {
+    /// The status and headers waiting to be sent.
+    start: Option<(u16, Headers)>,
+
+    /// If the status and headers have been sent.
+    started: bool,
+}
+
+/// The application thread's end of the request.
+struct Bridge {
+    commands: Sender<Command>,
+    replies: Receiver<Reply>,
+
+    /// The waker of the awaitable if it is waiting for a command.
+    waker: Arc<Mutex<Option<PyObject>>>,
+
+    /// The event loop the awaitable is running on.
+    event_loop: PyObject,
+
+    response: Mutex<ResponseState>,
+}
+
+impl Bridge {
+    /// Sends the command to the awaitable and wakes it.
+    fn notify(&self, py: Python, command: Command) -> PyResult<()> {
+        self.commands
+            .send(command)
+            .map_err(|_| disconnected_error())?;
+
+        let waker = self.waker.lock().unwrap().take();
+        if let Some(waker) = waker {
+            self.event_loop
+                .call_method1(py, "call_soon_threadsafe", (waker,))?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends the command to the awaitable and blocks until it answers,
+    /// releasing the GIL while waiting.
+    fn request(&self, py: Python, command: Command) -> PyResult<ReceiverPayload> {
+        self.notify(py, command)?;
+
+        let replies = &self.replies;
+        match py.allow_threads(|| replies.recv()) {
+            Ok(Reply::Chunk(payload)) => Ok(payload),
+            Ok(Reply::Sent) => Ok((false, PyBytes::new(py, b"").into())),
+            Ok(Reply::Error(e)) => Err(e),
+            Err(_) => Err(disconnected_error()),
+        }
+    }
+
+    /// Sends the status and headers if they have not been sent yet.
+    fn ensure_started(&self, py: Python) -> PyResult<()> {
+        let start = {
+            let mut response = self.response.lock().unwrap();
+            if response.started {
+                return Ok(());
+            }
+
+            match response.start.take() {
+                Some(start) => {
+                    response.started = true;
+                    start
+                },
+                None => {
+                    return Err(PyRuntimeError::new_err(
+                        "the response was written before calling start_response()",
+                    ))
+                },
+            }
+        };
+
+        self.request(py, Command::Start(start.0, start.1))?;
+        Ok(())
+    }
+
+    /// Writes a chunk of the response body, sending the status and headers
+    /// first if needed.
+    fn write(&self, py: Python, body: Vec<u8>) -> PyResult<()> {
+        self.ensure_started(py)?;
+
+        if !body.is_empty() {
+            self.request(py, Command::Body(body))?;
+        }
+
+        Ok(())
+    }
+
+    /// Completes the response.
+    fn finish(&self, py: Python) -> PyResult<()> {
+        self.ensure_started(py)?;
+        self.notify(py, Command::Finish)
+    }
+}
+
+/// The `start_response` callable given to the application.
+#[pyclass]
+pub struct StartResponse {
+    bridge: Arc<Bridge>,
+}
+
+#[pymethods]
+impl StartResponse {
+    /// Sets the status and headers of the response.
+    ///
+    /// Returns:
+    ///     The legacy `write` callable.
+    #[call]
+    #[args(exc_info = "None")]
+    fn __call__(
+        &self,
+        py: Python,
+        status: &str,
+        headers: Vec<(&str, &str)>,
+        exc_info: Option<PyObject>,
+    ) -> PyResult<Write> {
+        let mut response = self.bridge.response.lock().unwrap();
+
+        match exc_info {
+            Some(info) if response.started => {
+                return Err(PyErr::from_instance(info.as_ref(py).get_item(1)?));
+            },
+            None if response.start.is_some() | response.started => {
+                return Err(PyRuntimeError::new_err(
+                    "start_response() called again without exc_info",
+                ));
+            },
+            _ => {},
+        }
+
+        let status = status
+            .split(' ')
+            .next()
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| PyValueError::new_err("invalid status given"))?;
+
+        let encode = |s: &str| -> PyResult<Vec<u8>> {
+            s.chars()
+                .map(|c| {
+                    if (c as u32) < 256 {
+                        Ok(c as u8)
+                    } else {
+                        Err(PyValueError::new_err("headers must be latin-1 strings"))
+                    }
+                })
+                .collect()
+        };
+
+        let headers = headers
+            .into_iter()
+            .map(|(name, value)| Ok((encode(name)?, encode(value)?)))
+            .collect::<PyResult<Headers>>()?;
+
+        response.start = Some((status, headers));
+
+        Ok(Write {
+            bridge: self.bridge.clone(),
+        })
+    }
+}
+
+/// The legacy `write` callable returned by `start_response`.
+#[pyclass]
+pub struct Write {
+    bridge: Arc<Bridge>,
+}
+
+#[pymethods]
+impl Write {
+    /// Writes a chunk of the response body, blocking until it has been
+    /// sent.
+    #[call]
+    fn __call__(&self, py: Python, body: Vec<u8>) -> PyResult<()> {
+        self.bridge.write(py, body)
+    }
+}
+
+/// The `wsgi.input` stream reading the request body.
+#[pyclass]
+pub struct WSGIInput {
+    bridge: Arc<Bridge>,
+
+    /// Received data not read by the application yet.
+    buffer: Vec<u8>,
+
+    /// If the final chunk of the body has been received.
+    eof: bool,
+}
+
+impl WSGIInput {
+    /// Receives the next chunk of the body into the buffer, returning
+    /// `false` once the whole body has been received.
+    fn fill(&mut self, py: Python) -> PyResult<bool> {
+        if self.eof {
+            return Ok(false);
+        }
+
+        let (more_body, body) = self.bridge.request(py, Command::Read)?;
+        self.buffer.extend_from_slice(body.as_ref(py).as_bytes());
+        self.eof = !more_body;
+
+        Ok(true)
+    }
+
+    /// Removes up to `size` bytes from the front of the buffer.
+    fn take(&mut self, py: Python, size: usize) -> PyObject {
+        let size = size.min(self.buffer.len());
+        let out = PyBytes::new(py, &self.buffer[..size]);
+        self.buffer.drain(..size);
+        out.into()
+    }
+}
+
+#[pymethods]
+impl WSGIInput {
+    /// Reads up to `size` bytes of the body, reading the rest of the body
+    /// if `size` is `None` or negative.
+    #[args(size = "None")]
+    fn read(&mut self, py: Python, size: Option<isize>) -> PyResult<PyObject> {
+        let size = match size {
+            Some(size) if size >= 0 => size as usize,
+            _ => usize::MAX,
+        };
+
+        while (self.buffer.len() < size) && self.fill(py)? {}
+
+        Ok(self.take(py, size))
+    }
+
+    /// Reads a line of the body, reading up to `size` bytes if given.
+    #[args(size = "None")]
+    fn readline(&mut self, py: Python, size: Option<isize>) -> PyResult<PyObject> {
+        let size = match size {
+            Some(size) if size >= 0 => size as usize,
+            _ => usize::MAX,
+        };
+
+        let mut searched = 0;
+        loop {
+            if let Some(pos) = self.buffer[searched..].iter().position(|b| *b == b'\n') {
+                return Ok(self.take(py, (searched + pos + 1).min(size)));
+            }
+
+            searched = self.buffer.len();
+            if (searched >= size) || !self.fill(py)? {
+                return Ok(self.take(py, size));
+            }
+        }
+    }
+
+    /// Reads the remaining lines of the body.
+    #[args(_hint = "None")]
+    fn readlines(
+        &mut self,
+        py: Python,
+        _hint: Option<isize>,
+    ) -> PyResult<Vec<PyObject>> {
+        let mut lines = Vec::new();
+        loop {
+            let line = self.readline(py, None)?;
+            if line.as_ref(py).len()? == 0 {
+                return Ok(lines);
+            }
+
+            lines.push(line);
+        }
+    }
+}
+
+#[pyproto]
+impl PyIterProtocol for WSGIInput {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __next__(slf: &PyCell<Self>) -> PyResult<Option<PyObject>> {
+        let py = slf.py();
+        let mut slf = slf.try_borrow_mut()?;
+        let line = slf.readline(py, None)?;
+
+        if line.as_ref(py).len()? == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(line))
+        }
+    }
+}
+
+/// A command being carried out on the event loop.
+enum Pending {
+    Receive(ReceiveAwaitable),
+    Send(SendAwaitable),
+}
+
+/// The awaitable returned by the adapter, carrying out the commands of
+/// the application thread on the event loop.
+#[pyclass]
+pub struct WSGIAwaitable {
+    sender: Py<DataSender>,
+    receiver: Py<DataReceiver>,
+
+    commands: Receiver<Command>,
+    replies: Sender<Reply>,
+
+    /// The waker the application thread invokes after sending a command.
+    waker: Arc<Mutex<Option<PyObject>>>,
+
+    /// The command currently being carried out.
+    pending: Option<Pending>,
+
+    /// A command to carry out once the pending one completes.
+    queued: Option<Command>,
+
+    /// The future yielded while waiting for a command.
+    waiter: Option<PyObject>,
+
+    /// If the status and headers have been sent.
+    response_started: bool,
+
+    /// If the application has finished and the final chunk of the body
+    /// is being sent.
+    finished: bool,
+}
+
+impl WSGIAwaitable {
+    /// Starts carrying out the given command.
+    fn begin(&mut self, py: Python, command: Command) -> PyResult<()> {
+        let sender = self.sender.as_ref(py);
+
+        let pending = match command {
+            Command::Read => Pending::Receive(ReceiveAwaitable::new(
+                self.receiver.clone_ref(py),
+                None,
+            )),
+            Command::Start(status, headers) => {
+                let headers = headers
+                    .iter()
+                    .map(|(name, value)| (name.as_slice(), value.as_slice()))
+                    .collect();
+
+                self.response_started = true;
+                Pending::Send(DataSender::send_start(
+                    sender.borrow_mut(),
+                    status,
+                    headers,
+                )?)
+            },
+            Command::Body(body) => {
+                Pending::Send(DataSender::send_body(sender.borrow(), true, body)?)
+            },
+            Command::Finish => {
+                self.finished = true;
+                Pending::Send(DataSender::send_body(sender.borrow(), false, Vec::new())?)
+            },
+            // The error was caused by the client disconnecting.
+            Command::Failed(_) if sender.borrow().is_disconnected() => {
+                self.finished = true;
+                return Ok(());
+            },
+            Command::Failed(e) if self.response_started => {
+                error!("WSGI application raised an error mid response: {}", e);
+
+                self.finished = true;
+                Pending::Send(DataSender::abort(sender.borrow())?)
+            },
+            Command::Failed(e) => {
+                error!("WSGI application raised an error: {}", e);
+
+                self.response_started = true;
+                self.queued = Some(Command::Finish);
+                Pending::Send(DataSender::send_start(
+                    sender.borrow_mut(),
+                    500,
+                    vec![(b"content-length", b"0")],
+                )?)
+            },
+        };
+
+        self.pending = Some(pending);
+        Ok(())
+    }
+
+    /// Polls the command being carried out, answering the application
+    /// thread once it completes.
+    fn poll_pending(&mut self, py: Python) -> Poll<()> {
+        let reply = match self.pending.as_mut() {
+            Some(Pending::Receive(inner)) => match inner.poll(py) {
+                Ok(IterNextOutput::Yield(fut)) => return Ok(IterNextOutput::Yield(fut)),
+                Ok(IterNextOutput::Return(payload)) => Reply::Chunk(payload),
+                Err(e) => Reply::Error(e),
+            },
+            Some(Pending::Send(inner)) => match inner.poll(py) {
+                Ok(IterNextOutput::Yield(fut)) => return Ok(IterNextOutput::Yield(fut)),
+                Ok(IterNextOutput::Return(())) => Reply::Sent,
+                Err(e) if self.finished => return Err(e),
+                Err(e) => Reply::Error(e),
+            },
+            None => return Ok(IterNextOutput::Return(())),
+        };
+
+        self.pending = None;
+
+        // The application thread stops listening if it is cancelled.
+        let _ = self.replies.send(reply);
+
+        Ok(IterNextOutput::Return(()))
+    }
+}
+
+impl Awaitable for WSGIAwaitable {
+    type Output = ();
+
+    fn waiter(&mut self) -> &mut Option<PyObject> {
+        &mut self.waiter
+    }
+
+    fn poll_ready(&mut self, py: Python) -> Poll<()> {
+        loop {
+            if let IterNextOutput::Yield(fut) = self.poll_pending(py)? {
+                return Ok(IterNextOutput::Yield(fut));
+            }
+
+            if self.finished {
+                return Ok(IterNextOutput::Return(()));
+            }
+
+            if let Some(command) = self.queued.take() {
+                self.begin(py, command)?;
+                continue;
+            }
+
+            let command = match self.commands.try_recv() {
+                Ok(command) => command,
+                Err(TryRecvError::Disconnected) => {
+                    return Err(PyRuntimeError::new_err(
+                        "the WSGI application thread exited unexpectedly",
+                    ))
+                },
+                Err(TryRecvError::Empty) => {
+                    // The waiter is created before taking the lock as creating
+                    // it may release the GIL to the application thread.
+                    let (fut, waker) = create_waiter(py)?;
+
+                    let mut slot = self.waker.lock().unwrap();
+                    match self.commands.try_recv() {
+                        Ok(command) => command,
+                        Err(_) => {
+                            *slot = Some(waker);
+                            drop(slot);
+
+                            return self.wait_on(py, fut);
+                        },
+                    }
+                },
+            };
+
+            self.begin(py, command)?;
+        }
+    }
+}
+
+#[pyproto]
+impl PyAsyncProtocol for WSGIAwaitable {
+    fn __await__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+}
+
+#[pyproto]
+impl PyIterProtocol for WSGIAwaitable {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __next__(slf: &PyCell<Self>) -> Poll<PyObject> {
+        advance(slf)
+    }
+}
@@ -2,4 +2,4 @@ from .overrides import *
 from .litmus import *  # Overriding import
 from .adapters import LSGIToASGIAdapter
 from .shared import Server
-
+from .testing import TestClient
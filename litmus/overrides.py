@@ -1,13 +1,67 @@
 from typing import Optional
 
 
-def init_logger(level: str, log_file: Optional[str], pretty: bool):  # noqa
+def init_logger(
+    level: str,
+    log_file: Optional[str],
+    pretty: bool,
+    access_log: Optional[str] = None,
+    format: str = "text",
+    rotate_bytes: Optional[int] = None,
+    rotate_when: Optional[str] = None,
+    retention: int = 7,
+    compress: bool = False,
+):  # noqa
     """
     Sets the internal server log level.
 
     Levels: [error, warning, info, debug, trace]
 
-    trace levels log all internal server timings.
+    Access logs are written to `access_log` regardless of the level, `-`
+    writes them to stdout and `None` disables them. The format of each
+    line is set by the server's `access_log_format`.
+
+    The `json` format writes each record as a JSON object on a single line
+    containing the timestamp, level, target, message and any structured
+    fields of the record such as the connection index and client address.
+
+    Log files are rotated once they would grow past `rotate_bytes` or when
+    the `hourly` or `daily` period given by `rotate_when` changes, keeping
+    `retention` rotated files which are gzipped if `compress` is set.
+    Files are reopened when the process receives a `SIGUSR1`.
+    """
+    ...
+
+
+def reopen_logs():  # noqa
+    """
+    Reopens the log files, for use with external tools like logrotate on
+    platforms without `SIGUSR1`.
     """
     ...
 
+
+
+def init_tracing(
+    endpoint: str = "http://localhost:4318/v1/traces",
+    service_name: str = "litmus",
+    level: str = "info",
+):  # noqa
+    """
+    Exports tracing spans to an OpenTelemetry collector over OTLP/HTTP.
+
+    Each connection gets a span with its index and client address and each
+    request a span within it with the method, path and response status.
+    Internal timings of polling the sockets are `trace` level spans.
+
+    This requires litmus to be built with the `otlp` feature, otherwise a
+    `RuntimeError` is raised.
+    """
+    ...
+
+
+def shutdown_tracing():  # noqa
+    """
+    Exports any pending spans, this should be called before exiting.
+    """
+    ...
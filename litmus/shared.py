@@ -1,5 +1,5 @@
 import asyncio
-from typing import List
+from typing import List, Optional
 from functools import partial
 
 from . import _Server, create_server
@@ -41,29 +41,81 @@ class Server:
         app_callback,
         listen_on: List[str] = "127.0.0.1:8080",
         backlog: int = 1024,
-        keep_alive: int = 5,
+        keep_alive: float = 5,
+        header_timeout: float = 10,
+        body_timeout: float = 30,
+        write_timeout: float = 30,
+        max_connections: Optional[int] = None,
+        reject_overflow: bool = False,
+        max_requests_per_connection: Optional[int] = None,
+        root_path: str = "",
+        forwarded_prefix: bool = False,
+        trusted_proxies: Optional[List[str]] = None,
+        proxy_protocol: Optional[List[str]] = None,
+        cancel_on_disconnect: bool = False,
+        cancel_on_shutdown: bool = True,
+        cancel_grace_period: float = 0,
+        shutdown_timeout: Optional[float] = 30,
+        lifespan: str = "auto",
+        access_log_format: str = "combined",
+        metrics_bind: Optional[str] = None,
+        echo_request_id: bool = False,
+        health_path: Optional[str] = None,
+        ready_path: Optional[str] = None,
+        heartbeat_timeout: float = 10,
+        stall_threshold: Optional[float] = 3,
         gc_interval: int = 60,
         keep_alive_interval: int = 1,
     ):
         if isinstance(listen_on, str):
             listen_on = [listen_on]
 
+        # The loop's heartbeat is the keep alive tick so anything shorter
+        # would report every tick as a stall.
+        if stall_threshold is not None and stall_threshold <= keep_alive_interval:
+            raise ValueError("stall_threshold must be longer than keep_alive_interval")
+
         self.app = app_callback
         self.loop = asyncio.get_running_loop()
         self.gc_interval = gc_interval
         self.keep_alive_interval = keep_alive_interval
+        self.cancel_on_shutdown = cancel_on_shutdown
+        self.cancel_grace_period = cancel_grace_period
+        self.shutdown_timeout = shutdown_timeout
 
         if hasattr(asyncio, "ProactorEventLoop") and isinstance(self.loop, asyncio.ProactorEventLoop):
             raise TypeError("the asyncio.ProactorEventLoop event loop is not supported")
 
         self._waiter = self.loop.create_future()
         self._shutdown = False
+        self._drain_task = None
+        self._startup_task = None
 
         self._server = create_server(
             self.__app,
             listen_on,
             backlog,
             keep_alive,
+            header_timeout,
+            body_timeout,
+            write_timeout,
+            max_connections,
+            reject_overflow,
+            max_requests_per_connection,
+            root_path,
+            forwarded_prefix,
+            trusted_proxies or [],
+            proxy_protocol or [],
+            cancel_on_disconnect,
+            cancel_grace_period,
+            lifespan,
+            access_log_format,
+            metrics_bind,
+            echo_request_id,
+            health_path,
+            ready_path,
+            heartbeat_timeout,
+            stall_threshold,
         )
         self._server.init(
             self._add_reader,
@@ -77,27 +129,15 @@ class Server:
     def _poll_keep_alive(self):
         self._server.poll_keep_alive()
 
-        if not self._shutdown:
-            self.loop.call_later(
-                self.keep_alive_interval,
-                self._poll_keep_alive,
-            )
+        # Keeps running while draining so timeouts are still enforced.
+        self._kai_task = self.loop.call_later(
+            self.keep_alive_interval,
+            self._poll_keep_alive,
+        )
 
     def __app(self, scope, send, receive):
-        scope = {
-            "type": scope[0],
-            "http_version": scope[1],
-            "method": scope[2],
-            "scheme": scope[3],
-            "path": scope[4],
-            "query": scope[5],
-            "root_path": scope[6],
-            "headers": scope[7],
-            "client": scope[8],
-            "server": scope[9],
-        }
-
-        self.loop.create_task(self.app(scope, send, receive))
+        # Adapters may return awaitables which are not coroutines.
+        return asyncio.ensure_future(self.app(scope, send, receive), loop=self.loop)
 
     @property
     def _add_reader(self):
@@ -129,14 +169,70 @@ class Server:
         self.loop.add_reader(fd, self._server.poll_accept, index)
 
     def ignite(self):
-        self._server.ignite(self._register_listener)
+        """
+        Runs the application's lifespan startup and starts accepting
+        connections once it has completed.
+
+        If the application fails to start `run_forever` raises the error.
+        """
+        if self._startup_task is None:
+            self._startup_task = self.loop.create_task(self._startup())
+
+    async def _startup(self):
+        try:
+            await self._server.lifespan_startup()
+        except Exception as e:
+            self._shutdown = True
+            self._kai_task.cancel()
+            self._server.shutdown()
+            self._waiter.set_exception(e)
+            return
+
+        if not self._shutdown:
+            self._server.ignite(self._register_listener)
 
     def shutdown(self):
-        self._server.shutdown()
+        """
+        Stops accepting new connections and shuts the server down once the
+        requests currently being handled have finished.
+
+        Requests still running after `shutdown_timeout` seconds are
+        cancelled if `cancel_on_shutdown` is set and given the same time
+        again to clean up, a timeout of `None` waits forever.
+        """
+        if self._shutdown:
+            return
+
         self._shutdown = True
-        self._gci_task.cancel()
+        self._drain_task = self.loop.create_task(self._drain())
+
+    async def _drain(self):
+        tasks = self._server.drain()
+
+        if tasks:
+            _, pending = await asyncio.wait(tasks, timeout=self.shutdown_timeout)
+
+            if pending and self.cancel_on_shutdown:
+                for task in pending:
+                    task.cancel()
+                await asyncio.wait(pending, timeout=self.shutdown_timeout)
+
         self._kai_task.cancel()
-        self._waiter.set_result(None)
+        self._server.shutdown()
+
+        try:
+            await self._server.lifespan_shutdown()
+        except Exception as e:
+            self._waiter.set_exception(e)
+        else:
+            self._waiter.set_result(None)
+
+    def metrics(self) -> dict:
+        """
+        A snapshot of the server's connection, request and traffic counters,
+        the same metrics served on `metrics_bind` in the Prometheus format.
+        """
+        return self._server.metrics()
 
     async def run_forever(self):
         await self._waiter
@@ -0,0 +1,200 @@
+import asyncio
+from typing import Optional
+
+from .shared import Server
+
+
+class TestConnection:
+    """
+    A connection to the application held in memory, raw bytes are sent as
+    the client and the exact bytes the server writes are read back.
+
+    The server handles the connection the same as one from a listener, so
+    the keep alive, request timeouts and connection limits all apply.
+
+    Args:
+        server:
+            The native server to connect to.
+        client:
+            The address the connection appears to come from.
+        server_addr:
+            The address the connection appears to be made to.
+        tls:
+            If the connection appears to be over TLS.
+        timeout:
+            How long `request` waits for the server to respond in seconds.
+    """
+
+    __test__ = False
+
+    def __init__(self, server, client: str, server_addr: str, tls: bool, timeout: float):
+        self._loop = asyncio.get_running_loop()
+        self._timeout = timeout
+        self._scheduled = False
+        self._conn = server.test_connection(client, server_addr, tls, self._wake)
+
+    def _wake(self):
+        # Called by the server whenever the event loop would start polling
+        # a socket, the connection is polled on the loop's next iteration.
+        if not self._scheduled:
+            self._scheduled = True
+            self._loop.call_soon(self._pump)
+
+    def _pump(self):
+        self._scheduled = False
+        self._conn.pump()
+
+    @property
+    def closed(self) -> bool:
+        return self._conn.closed
+
+    def send(self, data: bytes):
+        """
+        Sends raw bytes to the server without waiting for a response.
+        """
+        self._conn.send(data)
+
+    def receive(self) -> bytes:
+        """
+        Returns everything the server has written since the last call
+        without waiting.
+        """
+        return self._conn.receive()
+
+    async def request(self, data: bytes) -> bytes:
+        """
+        Sends the raw request and returns the raw response once the server
+        is ready for the next request or has closed the connection.
+
+        Raises `asyncio.TimeoutError` if the server has not finished
+        responding within the timeout.
+        """
+        self._conn.send(data)
+
+        loop = asyncio.get_running_loop()
+        deadline = loop.time() + self._timeout
+        response = b""
+        while True:
+            # The application runs on the loop so it has to be yielded to
+            # between checks.
+            response += self._conn.receive()
+            if self._conn.closed or (response and self._conn.idle):
+                return response
+
+            if loop.time() >= deadline:
+                raise asyncio.TimeoutError(
+                    f"no complete response within {self._timeout}s, got {response!r}"
+                )
+            await asyncio.sleep(0)
+
+    async def read_until_closed(self) -> bytes:
+        """
+        Returns everything the server writes until it closes the connection.
+
+        Raises `asyncio.TimeoutError` if the connection is still open after
+        the timeout.
+        """
+        loop = asyncio.get_running_loop()
+        deadline = loop.time() + self._timeout
+        response = b""
+        while True:
+            response += self._conn.receive()
+            if self._conn.closed:
+                return response
+
+            if loop.time() >= deadline:
+                raise asyncio.TimeoutError(
+                    f"connection still open after {self._timeout}s, got {response!r}"
+                )
+            await asyncio.sleep(0)
+
+    def close(self):
+        """
+        Closes the connection as the client.
+        """
+        self._conn.close()
+
+
+class TestClient:
+    """
+    Runs an application through the server's HTTP protocol in memory
+    without binding a port, the application's lifespan is started on
+    entering and shut down on exiting.
+
+        async with TestClient(app) as client:
+            response = await client.request(b"GET / HTTP/1.1\\r\\nhost: test\\r\\n\\r\\n")
+
+    Args:
+        app:
+            The LSGI application callback, the same as given to `Server`.
+        client:
+            The address connections appear to come from.
+        server:
+            The address connections appear to be made to.
+        tls:
+            If connections appear to be over TLS.
+        timeout:
+            How long requests wait for the server to respond in seconds.
+        **settings:
+            Any other `Server` settings.
+    """
+
+    __test__ = False
+
+    def __init__(
+        self,
+        app,
+        client: str = "127.0.0.1:50000",
+        server: str = "127.0.0.1:8080",
+        tls: bool = False,
+        timeout: float = 5,
+        **settings,
+    ):
+        self.app = app
+        self.client = client
+        self.server_addr = server
+        self.tls = tls
+        self.timeout = timeout
+        self.settings = settings
+        self.server: Optional[Server] = None
+
+    async def __aenter__(self):
+        self.server = Server(self.app, listen_on=[], **self.settings)
+        self.server.ignite()
+        await self.server._startup_task
+
+        # Surfaces the error if the application failed to start.
+        if self.server._waiter.done():
+            await self.server._waiter
+
+        return self
+
+    async def __aexit__(self, *exc_info):
+        self.server.shutdown()
+        await self.server.run_forever()
+
+    def connect(self) -> TestConnection:
+        """
+        Opens a new connection which can be kept alive between requests.
+        """
+        if self.server is None:
+            raise RuntimeError("the client must be entered with `async with` first")
+
+        return TestConnection(
+            self.server._server,
+            self.client,
+            self.server_addr,
+            self.tls,
+            self.timeout,
+        )
+
+    async def request(self, data: bytes) -> bytes:
+        """
+        Sends the raw request on a new connection and returns the raw
+        response.
+        """
+        conn = self.connect()
+        try:
+            return await conn.request(data)
+        finally:
+            conn.close()
@@ -0,0 +1,89 @@
+use std::fmt::{self, Write};
+
+use chrono::SecondsFormat;
+use log::kv::{self, Key, Value, VisitSource};
+use log::Record;
+
+/// Formats the record as a JSON object on a single line.
+///
+/// The object contains the timestamp, level, target and message of the
+/// record followed by any structured fields attached to it.
+pub fn format_record(message: &fmt::Arguments, record: &Record) -> String {
+    let mut out = String::with_capacity(256);
+
+    out.push('{');
+    push_pair(
+        &mut out,
+        "timestamp",
+        &chrono::Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+    );
+    out.push(',');
+    push_pair(&mut out, "level", record.level().as_str());
+    out.push(',');
+    push_pair(&mut out, "target", record.target());
+    out.push(',');
+    push_pair(&mut out, "message", &message.to_string());
+
+    let _ = record
+        .key_values()
+        .visit(&mut FieldVisitor { out: &mut out });
+    out.push('}');
+
+    out
+}
+
+/// Writes a `"key":"value"` pair with both sides escaped.
+fn push_pair(out: &mut String, key: &str, value: &str) {
+    push_string(out, key);
+    out.push(':');
+    push_string(out, value);
+}
+
+/// Writes the value as an escaped JSON string.
+fn push_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            },
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Appends the structured fields of a record to the JSON object, numbers
+/// and booleans are kept as is while anything else is written as a string.
+struct FieldVisitor<'a> {
+    out: &'a mut String,
+}
+
+impl<'kvs, 'a> VisitSource<'kvs> for FieldVisitor<'a> {
+    fn visit_pair(
+        &mut self,
+        key: Key<'kvs>,
+        value: Value<'kvs>,
+    ) -> Result<(), kv::Error> {
+        self.out.push(',');
+        push_string(self.out, key.as_str());
+        self.out.push(':');
+
+        if let Some(n) = value.to_u64() {
+            let _ = write!(self.out, "{}", n);
+        } else if let Some(n) = value.to_i64() {
+            let _ = write!(self.out, "{}", n);
+        } else if let Some(b) = value.to_bool() {
+            let _ = write!(self.out, "{}", b);
+        } else {
+            push_string(self.out, &value.to_string());
+        }
+
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+use std::io::Write;
 use std::str::FromStr;
 use std::time::Duration;
 
@@ -5,29 +6,96 @@ use fern::colors::{Color, ColoredLevelConfig};
 #[cfg(not(target_env = "msvc"))]
 use jemallocator::Jemalloc;
 use log::LevelFilter;
-use pyo3::exceptions::PyValueError;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
 use pyo3::prelude::*;
 use pyo3::wrap_pyfunction;
 
+mod json_log;
+#[cfg(feature = "otlp")]
+mod otlp;
+mod rotation;
+
 #[cfg(not(target_env = "msvc"))]
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
+use litmus_server::asgi::ASGIAdapter;
 use litmus_server::responders::{DataReceiver, DataSender};
 use litmus_server::server::Server;
-use litmus_server::settings::ServerSettings;
+use litmus_server::settings::{
+    AccessLogFormat,
+    IpNetwork,
+    LifespanMode,
+    ServerSettings,
+    ACCESS_LOG_TARGET,
+};
+use litmus_server::wsgi::WSGIAdapter;
 
-#[pyfunction]
+use crate::rotation::{RotateWhen, RotatingFile, Rotation};
+
+/// Converts a setting given in seconds into a duration, rejecting values
+/// which are negative, not a number or too large.
+fn seconds(name: &str, value: f64) -> PyResult<Duration> {
+    Duration::try_from_secs_f64(value).map_err(|_| {
+        PyValueError::new_err(format!(
+            "invalid {} {:?} expected a non-negative number of seconds",
+            name, value
+        ))
+    })
+}
+
+/// Opens a log file sink rotated according to the given settings.
+fn rotating_file(path: &str, rotation: &Rotation) -> PyResult<Box<dyn Write + Send>> {
+    Ok(Box::new(RotatingFile::open(path, rotation.clone())?))
+}
+
+#[pyfunction(
+    access_log = "None",
+    format = "\"text\"",
+    rotate_bytes = "None",
+    rotate_when = "None",
+    retention = "7",
+    compress = "false"
+)]
+#[allow(clippy::too_many_arguments)]
 pub fn init_logger(
     log_level: &str,
     log_file: Option<String>,
     pretty: bool,
+    access_log: Option<String>,
+    format: &str,
+    rotate_bytes: Option<u64>,
+    rotate_when: Option<String>,
+    retention: usize,
+    compress: bool,
 ) -> PyResult<()> {
     let level = match LevelFilter::from_str(log_level) {
         Ok(l) => l,
         Err(e) => return Err(PyValueError::new_err(e.to_string())),
     };
 
+    let json = match format {
+        "text" => false,
+        "json" => true,
+        _ => {
+            return Err(PyValueError::new_err(format!(
+                "invalid log format {:?} expected one of 'text' or 'json'",
+                format
+            )))
+        },
+    };
+
+    let rotation = Rotation {
+        max_bytes: rotate_bytes,
+        when: rotate_when
+            .as_deref()
+            .map(RotateWhen::from_str)
+            .transpose()
+            .map_err(PyValueError::new_err)?,
+        retention,
+        compress,
+    };
+
     let mut colours = ColoredLevelConfig::new();
 
     if pretty {
@@ -39,8 +107,15 @@ pub fn init_logger(
             .trace(Color::Cyan);
     }
 
-    let mut builder = fern::Dispatch::new()
-        .format(move |out, message, record| {
+    let server_log =
+        fern::Dispatch::new().filter(|metadata| metadata.target() != ACCESS_LOG_TARGET);
+
+    let server_log = if json {
+        server_log.format(|out, message, record| {
+            out.finish(format_args!("{}", json_log::format_record(message, record)))
+        })
+    } else {
+        server_log.format(move |out, message, record| {
             out.finish(format_args!(
                 "{} | {} | {:<5} - {}",
                 chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
@@ -49,12 +124,52 @@ pub fn init_logger(
                 message,
             ))
         })
+    };
+
+    let mut server_log = server_log
         .level(level)
         .level_for("compress", LevelFilter::Off)
         .chain(std::io::stdout());
 
-    if let Some(file) = log_file {
-        builder = builder.chain(fern::log_file(file)?);
+    if let Some(file) = log_file.as_deref() {
+        server_log = server_log.chain(rotating_file(file, &rotation)?);
+    }
+
+    let mut builder = fern::Dispatch::new().chain(server_log);
+
+    // Access logs are written as is to their own sink, `-` being stdout.
+    builder = match access_log.as_deref() {
+        None => builder.level_for(ACCESS_LOG_TARGET, LevelFilter::Off),
+        Some(sink) => {
+            let access_log = fern::Dispatch::new()
+                .filter(|metadata| metadata.target() == ACCESS_LOG_TARGET)
+                .level(LevelFilter::Info);
+
+            let access_log = if json {
+                access_log.format(|out, message, record| {
+                    out.finish(format_args!(
+                        "{}",
+                        json_log::format_record(message, record)
+                    ))
+                })
+            } else {
+                access_log
+                    .format(|out, message, _| out.finish(format_args!("{}", message)))
+            };
+
+            let access_log = if sink == "-" {
+                access_log.chain(std::io::stdout())
+            } else {
+                access_log.chain(rotating_file(sink, &rotation)?)
+            };
+
+            builder.chain(access_log)
+        },
+    };
+
+    #[cfg(unix)]
+    if log_file.is_some() | access_log.map(|sink| sink != "-").unwrap_or(false) {
+        rotation::reopen_on_sigusr1();
     }
 
     let _ = builder.apply();
@@ -62,19 +177,120 @@ pub fn init_logger(
     Ok(())
 }
 
+/// Reopens the log files, this is done automatically when the process
+/// receives a `SIGUSR1` on unix.
 #[pyfunction]
+pub fn reopen_logs() {
+    rotation::request_reopen();
+}
+
+/// Exports the server's connection and request spans to an OpenTelemetry
+/// collector over OTLP/HTTP.
+#[pyfunction(
+    endpoint = "\"http://localhost:4318/v1/traces\"",
+    service_name = "\"litmus\"",
+    level = "\"info\""
+)]
+pub fn init_tracing(endpoint: &str, service_name: &str, level: &str) -> PyResult<()> {
+    #[cfg(feature = "otlp")]
+    {
+        let level = level
+            .parse()
+            .map_err(|_| PyValueError::new_err(format!("invalid level {:?}", level)))?;
+
+        otlp::install(endpoint, service_name, level).map_err(PyRuntimeError::new_err)
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    {
+        let _ = (endpoint, service_name, level);
+        Err(PyRuntimeError::new_err(
+            "litmus was built without tracing support, rebuild it with the `otlp` feature",
+        ))
+    }
+}
+
+/// Exports any pending spans and stops the tracing exporter.
+///
+/// The GIL is released while the spans are flushed so Python threads keep
+/// running while the exporter waits on the collector.
+#[pyfunction]
+pub fn shutdown_tracing(py: Python) {
+    #[cfg(feature = "otlp")]
+    py.allow_threads(otlp::shutdown);
+
+    #[cfg(not(feature = "otlp"))]
+    let _ = py;
+}
+
+#[pyfunction]
+#[allow(clippy::too_many_arguments)]
 pub fn create_server(
     callback: PyObject,
     binders: Vec<&str>,
     backlog: usize,
-    keep_alive: u64,
+    keep_alive: f64,
+    header_timeout: f64,
+    body_timeout: f64,
+    write_timeout: f64,
+    max_connections: Option<usize>,
+    reject_overflow: bool,
+    max_requests_per_connection: Option<usize>,
+    root_path: &str,
+    forwarded_prefix: bool,
+    trusted_proxies: Vec<&str>,
+    proxy_protocol: Vec<&str>,
+    cancel_on_disconnect: bool,
+    cancel_grace_period: f64,
+    lifespan: &str,
+    access_log_format: &str,
+    metrics_bind: Option<String>,
+    echo_request_id: bool,
+    health_path: Option<String>,
+    ready_path: Option<String>,
+    heartbeat_timeout: f64,
+    stall_threshold: Option<f64>,
 ) -> PyResult<Server> {
+    let trusted_proxies = match trusted_proxies
+        .into_iter()
+        .map(IpNetwork::from_str)
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(networks) => networks,
+        Err(e) => return Err(PyValueError::new_err(e)),
+    };
+
+    let lifespan = LifespanMode::from_str(lifespan).map_err(PyValueError::new_err)?;
+    let access_log_format =
+        AccessLogFormat::from_str(access_log_format).map_err(PyValueError::new_err)?;
+
     let settings = ServerSettings {
         backlog,
-        keep_alive: Duration::from_secs(keep_alive),
+        keep_alive: seconds("keep_alive", keep_alive)?,
+        header_timeout: seconds("header_timeout", header_timeout)?,
+        body_timeout: seconds("body_timeout", body_timeout)?,
+        write_timeout: seconds("write_timeout", write_timeout)?,
+        max_connections,
+        reject_overflow,
+        max_requests_per_connection,
+        root_path: root_path.trim_end_matches('/').to_string(),
+        forwarded_prefix,
+        trusted_proxies,
+        cancel_on_disconnect,
+        cancel_grace_period: seconds("cancel_grace_period", cancel_grace_period)?,
+        lifespan,
+        access_log_format,
+        metrics_bind,
+        echo_request_id,
+        health_path,
+        ready_path,
+        heartbeat_timeout: seconds("heartbeat_timeout", heartbeat_timeout)?,
+        stall_threshold: stall_threshold
+            .map(|t| seconds("stall_threshold", t))
+            .transpose()?,
     };
 
-    let server = Server::connect(settings, callback, binders)?;
+    let server = Server::connect(settings, callback, binders, proxy_protocol)?;
 
     Ok(server)
 }
@@ -83,8 +299,13 @@ pub fn create_server(
 fn litmus(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(create_server, m)?)?;
     m.add_function(wrap_pyfunction!(init_logger, m)?)?;
+    m.add_function(wrap_pyfunction!(reopen_logs, m)?)?;
+    m.add_function(wrap_pyfunction!(init_tracing, m)?)?;
+    m.add_function(wrap_pyfunction!(shutdown_tracing, m)?)?;
     m.add_class::<Server>()?;
     m.add_class::<DataSender>()?;
     m.add_class::<DataReceiver>()?;
+    m.add_class::<ASGIAdapter>()?;
+    m.add_class::<WSGIAdapter>()?;
     Ok(())
 }
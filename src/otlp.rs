@@ -0,0 +1,59 @@
+use std::sync::Mutex;
+
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Layer;
+
+/// The installed provider, kept so pending spans can be flushed on exit.
+static PROVIDER: Mutex<Option<SdkTracerProvider>> = Mutex::new(None);
+
+/// Installs a global tracing subscriber exporting spans at or above the
+/// given level to the OTLP/HTTP endpoint.
+///
+/// Spans are exported in batches from a background thread so the event
+/// loop is never blocked by the collector.
+pub fn install(
+    endpoint: &str,
+    service_name: &str,
+    level: LevelFilter,
+) -> Result<(), String> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|e| format!("failed to create OTLP exporter: {}", e))?;
+
+    let resource = Resource::builder()
+        .with_service_name(service_name.to_string())
+        .build();
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+
+    let layer = tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer("litmus"))
+        .with_filter(level);
+
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))
+        .map_err(|_| "tracing has already been initialised".to_string())?;
+
+    *PROVIDER.lock().unwrap() = Some(provider);
+
+    Ok(())
+}
+
+/// Exports any pending spans and stops the exporter.
+pub fn shutdown() {
+    let provider = PROVIDER.lock().unwrap().take();
+    if let Some(provider) = provider {
+        if let Err(e) = provider.shutdown() {
+            log::warn!("failed to flush pending spans: {}", e);
+        }
+    }
+}
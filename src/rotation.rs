@@ -0,0 +1,459 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::SystemTime;
+
+use chrono::{Duration, Local, TimeZone, Timelike};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+/// Bumped every time the log files should be reopened, each file reopens
+/// itself on the next write once it sees a new generation.
+static REOPEN_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Asks every log file to reopen its path on the next write, this lets
+/// an external tool like logrotate move the files away.
+pub fn request_reopen() {
+    REOPEN_GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Reopens the log files when the process receives a `SIGUSR1`.
+#[cfg(unix)]
+pub fn reopen_on_sigusr1() {
+    extern "C" fn handler(_: libc::c_int) {
+        // Only touches an atomic so it is safe to run in a signal handler.
+        request_reopen();
+    }
+
+    unsafe {
+        libc::signal(
+            libc::SIGUSR1,
+            handler as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+/// The period a time based rotation happens at.
+#[derive(Debug, Copy, Clone)]
+pub enum RotateWhen {
+    Hourly,
+    Daily,
+}
+
+impl RotateWhen {
+    /// The time the current period ends at, the file is rotated once it
+    /// has passed.
+    fn next_rotation(self) -> SystemTime {
+        let now = Local::now().naive_local();
+        let end = match self {
+            Self::Hourly => {
+                now.date().and_hms_opt(now.hour(), 0, 0).unwrap_or(now)
+                    + Duration::hours(1)
+            },
+            Self::Daily => {
+                now.date().and_hms_opt(0, 0, 0).unwrap_or(now) + Duration::days(1)
+            },
+        };
+
+        // A boundary skipped by a daylight saving change falls on the
+        // first time after the gap instead.
+        let end = Local
+            .from_local_datetime(&end)
+            .earliest()
+            .or_else(|| {
+                Local
+                    .from_local_datetime(&(end + Duration::hours(1)))
+                    .earliest()
+            })
+            .unwrap_or_else(Local::now);
+
+        end.into()
+    }
+}
+
+impl FromStr for RotateWhen {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hourly" => Ok(Self::Hourly),
+            "daily" => Ok(Self::Daily),
+            _ => Err(format!(
+                "invalid rotation period {:?} expected one of 'hourly' or 'daily'",
+                s
+            )),
+        }
+    }
+}
+
+/// When and how log files are rotated.
+#[derive(Debug, Clone)]
+pub struct Rotation {
+    /// Rotate once the file would grow past this many bytes.
+    pub max_bytes: Option<u64>,
+
+    /// Rotate when the period changes.
+    pub when: Option<RotateWhen>,
+
+    /// The number of rotated files kept, older files are deleted.
+    pub retention: usize,
+
+    /// If rotated files are compressed with gzip.
+    pub compress: bool,
+}
+
+/// A log file rotated according to the `Rotation` settings.
+///
+/// Rotated files are numbered like logrotate does, `<path>.1` being the
+/// most recent, with a `.gz` suffix when compressed.
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    file: File,
+
+    /// The size of the current file in bytes.
+    size: u64,
+
+    /// When the current file is due to be rotated by time.
+    rotate_at: Option<SystemTime>,
+
+    /// The reopen generation the file was opened at.
+    generation: usize,
+
+    /// If the last write ended a line, rotation only happens between
+    /// lines.
+    at_line_start: bool,
+
+    /// The number of files rotated so far, used to give each file waiting
+    /// to be compressed its own name.
+    rotations: usize,
+
+    /// The thread compressing the last rotated file.
+    compressing: Option<JoinHandle<()>>,
+}
+
+impl RotatingFile {
+    /// Opens the file at the given path in append mode.
+    pub fn open(path: impl Into<PathBuf>, rotation: Rotation) -> io::Result<Self> {
+        let path = path.into();
+        let file = open_append(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            rotate_at: rotation.when.map(RotateWhen::next_rotation),
+            path,
+            rotation,
+            file,
+            size,
+            generation: REOPEN_GENERATION.load(Ordering::Relaxed),
+            at_line_start: true,
+            rotations: 0,
+            compressing: None,
+        })
+    }
+
+    /// Reopens the path, creating the file if it was moved away.
+    fn reopen(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file = open_append(&self.path)?;
+        self.size = self.file.metadata()?.len();
+        self.rotate_at = self.rotation.when.map(RotateWhen::next_rotation);
+
+        Ok(())
+    }
+
+    /// Checks if the file should be rotated before writing the given
+    /// number of bytes.
+    fn should_rotate(&self, incoming: usize) -> bool {
+        if let Some(max) = self.rotation.max_bytes {
+            if (self.size > 0) & (self.size + incoming as u64 > max) {
+                return true;
+            }
+        }
+
+        match self.rotate_at {
+            Some(rotate_at) => SystemTime::now() >= rotate_at,
+            None => false,
+        }
+    }
+
+    /// Moves the current file to `<path>.1`, shifting the older files up
+    /// and deleting any past the retention count.
+    ///
+    /// When compressing, the file is moved aside and the shifting and
+    /// compression happen on a separate thread so writes are not held up,
+    /// each rotation waiting on the one before it.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        let retention = self.rotation.retention;
+        if retention == 0 {
+            fs::remove_file(&self.path)?;
+            return self.reopen();
+        }
+
+        if !self.rotation.compress {
+            shift_rotated(&self.path, retention, false)?;
+            fs::rename(&self.path, rotated_path(&self.path, 1, false))?;
+            return self.reopen();
+        }
+
+        self.rotations += 1;
+        let mut staged = self.path.clone().into_os_string();
+        staged.push(format!(".rotating.{}", self.rotations));
+        let staged = PathBuf::from(staged);
+        fs::rename(&self.path, &staged)?;
+        self.reopen()?;
+
+        let path = self.path.clone();
+        let previous = self.compressing.take();
+        self.compressing = Some(thread::spawn(move || {
+            if let Some(handle) = previous {
+                let _ = handle.join();
+            }
+
+            if let Err(e) = shift_rotated(&path, retention, true) {
+                log::error!("failed to shift the rotated files of {:?}: {}", path, e);
+                return;
+            }
+
+            let target = rotated_path(&path, 1, true);
+            if let Err(e) = compress(&staged, &target) {
+                log::error!(
+                    "failed to compress {:?}, it has been left as is: {}",
+                    staged,
+                    e
+                );
+                let _ = fs::remove_file(&target);
+            }
+        }));
+
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.at_line_start {
+            let generation = REOPEN_GENERATION.load(Ordering::Relaxed);
+            if generation != self.generation {
+                self.generation = generation;
+                self.reopen()?;
+            }
+
+            // The reopened file may already be due to be rotated.
+            if self.should_rotate(buf.len()) {
+                self.rotate()?;
+            }
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        self.at_line_start = buf[..written].ends_with(b"\n");
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// The path of the rotated file with the given number.
+fn rotated_path(path: &Path, number: usize, compressed: bool) -> PathBuf {
+    let mut path = path.to_path_buf().into_os_string();
+    path.push(format!(".{}", number));
+    if compressed {
+        path.push(".gz");
+    }
+
+    path.into()
+}
+
+/// Shifts the rotated files up by one, deleting the one which would go
+/// past the retention count.
+fn shift_rotated(path: &Path, retention: usize, compressed: bool) -> io::Result<()> {
+    let _ = fs::remove_file(rotated_path(path, retention, compressed));
+    for number in (1..retention).rev() {
+        let from = rotated_path(path, number, compressed);
+        if from.exists() {
+            fs::rename(&from, rotated_path(path, number + 1, compressed))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Compresses the file with gzip into the target path, removing the
+/// original once done.
+fn compress(from: &Path, to: &Path) -> io::Result<()> {
+    let mut source = File::open(from)?;
+    let mut encoder = GzEncoder::new(File::create(to)?, Compression::default());
+    io::copy(&mut source, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+
+    fs::remove_file(from)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    /// An empty directory for a single test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "litmus-rotation-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn by_size(max_bytes: u64, retention: usize, compress: bool) -> Rotation {
+        Rotation {
+            max_bytes: Some(max_bytes),
+            when: None,
+            retention,
+            compress,
+        }
+    }
+
+    fn read(path: impl AsRef<Path>) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    fn read_gz(path: impl AsRef<Path>) -> String {
+        let mut out = String::new();
+        GzDecoder::new(File::open(path).unwrap())
+            .read_to_string(&mut out)
+            .unwrap();
+        out
+    }
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn periods_are_parsed() {
+        assert!(matches!("hourly".parse(), Ok(RotateWhen::Hourly)));
+        assert!(matches!("daily".parse(), Ok(RotateWhen::Daily)));
+        assert!("weekly".parse::<RotateWhen>().is_err());
+    }
+
+    #[test]
+    fn next_rotation_is_within_the_period() {
+        let now = SystemTime::now();
+        for (when, period) in [
+            (RotateWhen::Hourly, 60 * 60),
+            (RotateWhen::Daily, 24 * 60 * 60),
+        ] {
+            let until = when.next_rotation().duration_since(now).unwrap();
+
+            // Allows for a daylight saving change within the period.
+            assert!(
+                until.as_secs() <= period + 60 * 60,
+                "{:?} in {:?}",
+                when,
+                until
+            );
+        }
+    }
+
+    #[test]
+    fn rotates_by_size_between_lines() {
+        let dir = test_dir("size");
+        let path = dir.join("app.log");
+        let mut file = RotatingFile::open(&path, by_size(10, 2, false)).unwrap();
+
+        file.write_all(b"first ").unwrap();
+        // Rotation never splits a line even when it grows past the size.
+        file.write_all(b"line\n").unwrap();
+        file.write_all(b"second\n").unwrap();
+        file.write_all(b"third\n").unwrap();
+        file.write_all(b"fourth\n").unwrap();
+        file.flush().unwrap();
+
+        assert_eq!(read(&path), "fourth\n");
+        assert_eq!(read(dir.join("app.log.1")), "third\n");
+        assert_eq!(read(dir.join("app.log.2")), "second\n");
+        assert_eq!(files(&dir), ["app.log", "app.log.1", "app.log.2"]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn zero_retention_deletes_the_file() {
+        let dir = test_dir("zero");
+        let path = dir.join("app.log");
+        let mut file = RotatingFile::open(&path, by_size(5, 0, false)).unwrap();
+
+        file.write_all(b"first\n").unwrap();
+        file.write_all(b"second\n").unwrap();
+        file.flush().unwrap();
+
+        assert_eq!(read(&path), "second\n");
+        assert_eq!(files(&dir), ["app.log"]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rotated_files_are_compressed() {
+        let dir = test_dir("compress");
+        let path = dir.join("app.log");
+        let mut file = RotatingFile::open(&path, by_size(5, 2, true)).unwrap();
+
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+        file.compressing.take().unwrap().join().unwrap();
+
+        assert_eq!(read(&path), "fourth\n");
+        assert_eq!(read_gz(dir.join("app.log.1.gz")), "third\n");
+        assert_eq!(read_gz(dir.join("app.log.2.gz")), "second\n");
+        assert_eq!(files(&dir), ["app.log", "app.log.1.gz", "app.log.2.gz"]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reopens_when_requested() {
+        let dir = test_dir("reopen");
+        let path = dir.join("app.log");
+        let mut file = RotatingFile::open(&path, by_size(1024, 1, false)).unwrap();
+
+        file.write_all(b"before\n").unwrap();
+        fs::rename(&path, dir.join("moved.log")).unwrap();
+
+        // Still written to the moved file until a reopen is requested.
+        file.write_all(b"moved\n").unwrap();
+        request_reopen();
+        file.write_all(b"after\n").unwrap();
+        file.flush().unwrap();
+
+        assert_eq!(read(dir.join("moved.log")), "before\nmoved\n");
+        assert_eq!(read(&path), "after\n");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
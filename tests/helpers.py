@@ -0,0 +1,63 @@
+"""
+Helpers shared by the request level tests.
+"""
+
+import asyncio
+
+
+def run(coro):
+    return asyncio.run(coro)
+
+
+def head_and_body(response: bytes):
+    """
+    Splits a raw response into its status line, lower cased headers and
+    the raw body.
+    """
+    head, _, body = response.partition(b"\r\n\r\n")
+    status, *headers = head.split(b"\r\n")
+    headers = dict(line.lower().split(b": ", 1) for line in headers)
+    return status, headers, body
+
+
+async def read_body(receive) -> bytes:
+    body = b""
+    while True:
+        more_body, chunk = await receive()
+        body += chunk
+        if not more_body:
+            return body
+
+
+async def app(scope, send, receive):
+    """
+    A small LSGI application responding according to the request path.
+    """
+    path = scope["path"]
+
+    if path == "/length":
+        await send.send_start(200, [(b"content-length", b"5")])
+        await send.send_body(False, b"hello")
+    elif path == "/stream":
+        await send.send_start(200, [])
+        await send.send_body(True, b"hello ")
+        await send.send_body(True, b"")
+        await send.send_body(False, b"world")
+    elif path == "/no-content":
+        await send.send_start(204, [])
+        await send.send_body(False, b"")
+    elif path == "/echo":
+        body = await read_body(receive)
+        await send.send_start(200, [(b"content-length", str(len(body)).encode())])
+        await send.send_body(False, body)
+    elif path == "/crash":
+        raise RuntimeError("crashed before responding")
+    elif path == "/silent":
+        return
+    elif path == "/half":
+        await send.send_start(200, [(b"content-length", b"10")])
+        await send.send_body(True, b"abc")
+        raise RuntimeError("crashed mid response")
+    else:
+        await send.send_start(404, [(b"content-length", b"0")])
+        await send.send_body(False, b"")
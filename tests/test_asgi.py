@@ -0,0 +1,92 @@
+"""
+Tests for running ASGI 3 applications through the `ASGIAdapter`.
+
+    pytest tests/
+"""
+
+from helpers import head_and_body, run
+from litmus import TestClient
+from litmus.adapters import ASGIAdapter
+
+
+def test_http_scope():
+    scopes = []
+
+    async def asgi_app(scope, receive, send):
+        scopes.append(scope)
+        await send({"type": "http.response.start", "status": 204, "headers": []})
+        await send({"type": "http.response.body"})
+
+    async def main():
+        async with TestClient(ASGIAdapter(asgi_app), lifespan="off") as client:
+            await client.request(b"GET /path?q=1 HTTP/1.1\r\n\r\n")
+
+        scope = scopes[0]
+        assert scope["type"] == "http"
+        assert scope["asgi"] == {"version": "3.0", "spec_version": "2.3"}
+        assert scope["extensions"] == {}
+        assert scope["path"] == "/path"
+        assert scope["query_string"] == b"q=1"
+
+    run(main())
+
+
+def test_request_and_disconnect_events():
+    events = []
+
+    async def asgi_app(scope, receive, send):
+        while True:
+            event = await receive()
+            events.append(event)
+            if not event.get("more_body"):
+                break
+
+        await send({
+            "type": "http.response.start",
+            "status": 200,
+            "headers": [(b"content-length", b"2")],
+        })
+        await send({"type": "http.response.body", "body": b"ok"})
+
+        # Once the response is complete the exchange is over.
+        events.append(await receive())
+
+    async def main():
+        async with TestClient(ASGIAdapter(asgi_app), lifespan="off") as client:
+            response = await client.request(
+                b"POST / HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n"
+                b"5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n"
+            )
+
+        status, headers, body = head_and_body(response)
+        assert status == b"HTTP/1.1 200 OK"
+        assert body == b"ok"
+
+        body = b"".join(event["body"] for event in events[:-1])
+        assert all(event["type"] == "http.request" for event in events[:-1])
+        assert body == b"hello world"
+        assert events[-1] == {"type": "http.disconnect"}
+
+    run(main())
+
+
+def test_body_before_start_is_rejected():
+    errors = []
+
+    async def asgi_app(scope, receive, send):
+        try:
+            await send({"type": "http.response.body", "body": b"early"})
+        except RuntimeError as e:
+            errors.append(e)
+
+        await send({"type": "http.response.start", "status": 204, "headers": []})
+        await send({"type": "http.response.body"})
+
+    async def main():
+        async with TestClient(ASGIAdapter(asgi_app), lifespan="off") as client:
+            response = await client.request(b"GET / HTTP/1.1\r\n\r\n")
+
+        assert response.startswith(b"HTTP/1.1 204 No Content\r\n")
+        assert len(errors) == 1
+
+    run(main())
@@ -0,0 +1,95 @@
+"""
+Tests for pausing reading the request body while the application is
+behind.
+
+    pytest tests/
+"""
+
+import asyncio
+
+from helpers import app, head_and_body, run
+from litmus import TestClient
+
+CHUNK = 32 * 1024
+
+
+def test_large_body_is_read_with_backpressure():
+    payload = bytes(range(256)) * 4096
+
+    async def main():
+        async with TestClient(app, lifespan="off") as client:
+            conn = client.connect()
+            conn.send(b"POST /echo HTTP/1.1\r\ncontent-length: %d\r\n\r\n" % len(payload))
+
+            # Sent in pieces so the application falls behind the client.
+            for start in range(0, len(payload) - CHUNK, CHUNK):
+                conn.send(payload[start:start + CHUNK])
+            response = await conn.request(payload[start + CHUNK:])
+            metrics = client.server.metrics()
+
+        status, headers, body = head_and_body(response)
+        assert status == b"HTTP/1.1 200 OK"
+        assert body == payload
+        assert metrics["read_backpressure_events"] > 0
+
+    run(main())
+
+
+def test_reading_resumes_once_the_application_catches_up():
+    payload = b"x" * (8 * CHUNK)
+    started = asyncio.Event()
+    received = []
+
+    async def slow(scope, send, receive):
+        started.set()
+        while True:
+            more_body, chunk = await receive()
+            received.append(len(chunk))
+            if not more_body:
+                break
+            await asyncio.sleep(0.01)
+
+        await send.send_start(200, [(b"content-length", b"0")])
+        await send.send_body(False, b"")
+
+    async def main():
+        async with TestClient(slow, lifespan="off") as client:
+            conn = client.connect()
+            conn.send(b"POST / HTTP/1.1\r\ncontent-length: %d\r\n\r\n" % len(payload))
+            await started.wait()
+
+            for start in range(0, len(payload), CHUNK):
+                conn.send(payload[start:start + CHUNK])
+
+            # Reading is paused so the body is held back rather than
+            # dropped while the application is behind.
+            assert sum(received) < len(payload)
+
+            response = await conn.request(b"")
+            metrics = client.server.metrics()
+
+        assert response.startswith(b"HTTP/1.1 200 OK\r\n")
+        assert sum(received) == len(payload)
+        assert metrics["read_backpressure_events"] > 0
+
+    run(main())
+
+
+def test_unread_body_does_not_reach_the_next_request():
+    async def main():
+        async with TestClient(app, lifespan="off") as client:
+            conn = client.connect()
+
+            # The application answers without reading the bodies.
+            for _ in range(3):
+                response = await conn.request(
+                    b"POST /length HTTP/1.1\r\ncontent-length: 4\r\n\r\nbody"
+                )
+                assert head_and_body(response)[2] == b"hello"
+
+            response = await conn.request(
+                b"POST /echo HTTP/1.1\r\ncontent-length: 4\r\n\r\nnext"
+            )
+            assert head_and_body(response)[2] == b"next"
+
+    run(main())
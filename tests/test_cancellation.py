@@ -0,0 +1,80 @@
+"""
+Tests for cancelling the application when the client disconnects.
+
+    pytest tests/
+"""
+
+import asyncio
+
+from helpers import run
+from litmus import TestClient
+
+
+def slow_app(events):
+    async def app(scope, send, receive):
+        try:
+            await asyncio.sleep(1)
+            events.append("finished")
+        except asyncio.CancelledError:
+            events.append("cancelled")
+            raise
+
+    return app
+
+
+def test_application_is_cancelled_on_disconnect():
+    events = []
+
+    async def main():
+        async with TestClient(
+            slow_app(events), lifespan="off", cancel_on_disconnect=True
+        ) as client:
+            conn = client.connect()
+            conn.send(b"GET / HTTP/1.1\r\n\r\n")
+            await asyncio.sleep(0.01)
+            conn.close()
+            await asyncio.sleep(0.05)
+
+            assert events == ["cancelled"]
+
+    run(main())
+
+
+def test_application_is_given_the_grace_period():
+    events = []
+
+    async def main():
+        async with TestClient(
+            slow_app(events),
+            lifespan="off",
+            cancel_on_disconnect=True,
+            cancel_grace_period=0.2,
+        ) as client:
+            conn = client.connect()
+            conn.send(b"GET / HTTP/1.1\r\n\r\n")
+            await asyncio.sleep(0.01)
+            conn.close()
+
+            await asyncio.sleep(0.1)
+            assert events == []
+
+            await asyncio.sleep(0.2)
+            assert events == ["cancelled"]
+
+    run(main())
+
+
+def test_application_keeps_running_unless_enabled():
+    events = []
+
+    async def main():
+        async with TestClient(slow_app(events), lifespan="off") as client:
+            conn = client.connect()
+            conn.send(b"GET / HTTP/1.1\r\n\r\n")
+            await asyncio.sleep(0.01)
+            conn.close()
+
+            await asyncio.sleep(0.1)
+            assert events == []
+
+    run(main())
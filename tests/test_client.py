@@ -0,0 +1,98 @@
+"""
+Tests for the in-memory `TestClient` driving applications through the
+server's connection handling without binding a port.
+
+    pytest tests/
+"""
+
+from helpers import app, head_and_body, run
+from litmus import TestClient
+
+
+def test_content_length_response():
+    async def main():
+        async with TestClient(app, lifespan="off") as client:
+            response = await client.request(b"GET /length HTTP/1.1\r\nhost: test\r\n\r\n")
+
+        status, headers, body = head_and_body(response)
+        assert status == b"HTTP/1.1 200 OK"
+        assert headers[b"content-length"] == b"5"
+        assert b"transfer-encoding" not in headers
+        assert body == b"hello"
+
+    run(main())
+
+
+def test_chunked_request_body():
+    async def main():
+        async with TestClient(app, lifespan="off") as client:
+            response = await client.request(
+                b"POST /echo HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n"
+                b"5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n"
+            )
+
+        assert head_and_body(response)[2] == b"hello world"
+
+    run(main())
+
+
+def test_connection_is_reused_between_requests():
+    async def main():
+        async with TestClient(app, lifespan="off") as client:
+            conn = client.connect()
+
+            for _ in range(3):
+                response = await conn.request(b"GET /length HTTP/1.1\r\n\r\n")
+                assert response.endswith(b"\r\n\r\nhello")
+                assert not conn.closed
+
+    run(main())
+
+
+def test_request_sent_in_pieces():
+    async def main():
+        async with TestClient(app, lifespan="off") as client:
+            conn = client.connect()
+            conn.send(b"GET /len")
+            conn.send(b"gth HTTP/1.1\r\n")
+            assert conn.receive() == b""
+
+            response = await conn.request(b"\r\n")
+            assert response.endswith(b"\r\n\r\nhello")
+
+    run(main())
+
+
+def test_connections_are_handled_by_the_server():
+    async def main():
+        async with TestClient(app, lifespan="off") as client:
+            first = client.connect()
+            second = client.connect()
+            assert client.server._server.len_clients() == 2
+
+            await first.request(b"GET /length HTTP/1.1\r\n\r\n")
+            await second.request(b"GET /length HTTP/1.1\r\n\r\n")
+            metrics = client.server.metrics()
+
+        assert metrics["connections_accepted"] == 2
+        assert metrics["requests"]["GET"]["2xx"] == 2
+
+    run(main())
+
+
+def test_client_close():
+    async def main():
+        async with TestClient(app, lifespan="off") as client:
+            conn = client.connect()
+            await conn.request(b"GET /length HTTP/1.1\r\n\r\n")
+            conn.close()
+
+            assert conn.closed
+            try:
+                conn.send(b"GET /length HTTP/1.1\r\n\r\n")
+            except ConnectionError:
+                pass
+            else:
+                raise AssertionError("sent on a closed connection")
+
+    run(main())
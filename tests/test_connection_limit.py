@@ -0,0 +1,56 @@
+"""
+Tests for the limit on how many connections are handled at once.
+
+    pytest tests/
+"""
+
+from helpers import app, head_and_body, run
+from litmus import TestClient
+
+
+def test_overflow_is_rejected_with_503():
+    async def main():
+        async with TestClient(
+            app, lifespan="off", max_connections=1, reject_overflow=True
+        ) as client:
+            first = client.connect()
+            overflow = client.connect()
+            response = await overflow.read_until_closed()
+
+            status, headers, body = head_and_body(response)
+            assert status == b"HTTP/1.1 503 Service Unavailable"
+            assert headers[b"connection"] == b"close"
+
+            # The connection already accepted is unaffected.
+            response = await first.request(b"GET /length HTTP/1.1\r\n\r\n")
+            assert response.endswith(b"hello")
+
+            metrics = client.server.metrics()
+            assert metrics["connections_accepted"] == 1
+            assert metrics["connections_rejected"] == 1
+
+    run(main())
+
+
+def test_overflow_waits_for_a_free_slot():
+    async def main():
+        async with TestClient(
+            app,
+            lifespan="off",
+            max_connections=1,
+            keep_alive_interval=0.05,
+            stall_threshold=None,
+        ) as client:
+            first = client.connect()
+            waiting = client.connect()
+            waiting.send(b"GET /length HTTP/1.1\r\n\r\n")
+            assert waiting.receive() == b""
+            assert client.server.metrics()["connections_accepted"] == 1
+
+            # Closing the first connection makes room for the waiting one.
+            first.close()
+            response = await waiting.request(b"")
+            assert head_and_body(response)[2] == b"hello"
+            assert client.server.metrics()["connections_accepted"] == 2
+
+    run(main())
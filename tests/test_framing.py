@@ -0,0 +1,154 @@
+"""
+Tests for how response bodies are framed when the application gives no
+content length.
+
+    pytest tests/
+"""
+
+from helpers import app, head_and_body, run
+from litmus import TestClient, WSGIAdapter
+from litmus.adapters import ASGIAdapter
+
+
+def test_stream_without_length_is_chunked():
+    async def main():
+        async with TestClient(app, lifespan="off") as client:
+            conn = client.connect()
+            response = await conn.request(b"GET /stream HTTP/1.1\r\nhost: test\r\n\r\n")
+
+            status, headers, body = head_and_body(response)
+            assert headers[b"transfer-encoding"] == b"chunked"
+            assert body == b"6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n"
+
+            # The chunked framing lets the connection be reused.
+            assert not conn.closed
+            response = await conn.request(b"GET /length HTTP/1.1\r\nhost: test\r\n\r\n")
+            assert response.endswith(b"hello")
+
+    run(main())
+
+
+def test_stream_without_length_over_http_10_is_close_delimited():
+    async def main():
+        async with TestClient(app, lifespan="off") as client:
+            conn = client.connect()
+            response = await conn.request(b"GET /stream HTTP/1.0\r\n\r\n")
+
+            status, headers, body = head_and_body(response)
+            assert b"transfer-encoding" not in headers
+            assert b"content-length" not in headers
+            assert body == b"hello world"
+            assert conn.closed
+
+    run(main())
+
+
+def test_no_content_has_no_body():
+    async def main():
+        async with TestClient(app, lifespan="off") as client:
+            response = await client.request(b"GET /no-content HTTP/1.1\r\n\r\n")
+
+        status, headers, body = head_and_body(response)
+        assert status == b"HTTP/1.1 204 No Content"
+        assert b"transfer-encoding" not in headers
+        assert body == b""
+
+    run(main())
+
+
+def wsgi_app(environ, start_response):
+    if environ["PATH_INFO"] == "/fail":
+        start_response("200 OK", [("content-type", "text/plain")])
+        yield b"partial"
+        raise RuntimeError("failed mid response")
+
+    start_response("200 OK", [("content-type", "text/plain")])
+    yield b"one "
+    yield b"two"
+
+
+def test_wsgi_generator_is_chunked():
+    async def main():
+        async with TestClient(WSGIAdapter(wsgi_app, threads=2), lifespan="off") as client:
+            response = await client.request(b"GET / HTTP/1.1\r\n\r\n")
+
+        status, headers, body = head_and_body(response)
+        assert headers[b"transfer-encoding"] == b"chunked"
+        assert body == b"4\r\none \r\n3\r\ntwo\r\n0\r\n\r\n"
+
+    run(main())
+
+
+def test_wsgi_failure_mid_response_closes_the_connection():
+    async def main():
+        async with TestClient(
+            WSGIAdapter(wsgi_app, threads=2),
+            lifespan="off",
+            keep_alive_interval=0.05,
+            stall_threshold=None,
+        ) as client:
+            conn = client.connect()
+            response = await conn.request(b"GET /fail HTTP/1.1\r\n\r\n")
+
+            # The body is left without its last chunk so the client can
+            # tell the response is incomplete.
+            assert head_and_body(response)[2] == b"7\r\npartial\r\n"
+            assert conn.closed
+
+    run(main())
+
+
+def test_asgi_stream_without_length_is_chunked():
+    async def asgi_app(scope, receive, send):
+        await send({"type": "http.response.start", "status": 200, "headers": []})
+        await send({"type": "http.response.body", "body": b"hello ", "more_body": True})
+        await send({"type": "http.response.body", "body": b"world"})
+
+    async def main():
+        async with TestClient(ASGIAdapter(asgi_app), lifespan="off") as client:
+            response = await client.request(b"GET / HTTP/1.1\r\n\r\n")
+
+        status, headers, body = head_and_body(response)
+        assert headers[b"transfer-encoding"] == b"chunked"
+        assert body == b"6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n"
+
+    run(main())
+
+
+def test_head_response_keeps_the_length_without_a_body():
+    async def main():
+        async with TestClient(app, lifespan="off") as client:
+            conn = client.connect()
+            response = await conn.request(b"HEAD /length HTTP/1.1\r\n\r\n")
+
+            status, headers, body = head_and_body(response)
+            assert status == b"HTTP/1.1 200 OK"
+            assert headers[b"content-length"] == b"5"
+            assert body == b""
+
+            # Nothing left over from the skipped body reaches the next
+            # response on the connection.
+            response = await conn.request(b"GET /length HTTP/1.1\r\n\r\n")
+            status, headers, body = head_and_body(response)
+            assert status == b"HTTP/1.1 200 OK"
+            assert body == b"hello"
+
+    run(main())
+
+
+def test_head_response_without_length_is_not_chunked():
+    async def main():
+        async with TestClient(app, lifespan="off") as client:
+            conn = client.connect()
+            response = await conn.request(b"HEAD /stream HTTP/1.1\r\n\r\n")
+
+            status, headers, body = head_and_body(response)
+            assert status == b"HTTP/1.1 200 OK"
+            assert b"transfer-encoding" not in headers
+            assert body == b""
+            assert not conn.closed
+
+            response = await conn.request(b"GET /length HTTP/1.1\r\n\r\n")
+            assert head_and_body(response)[2] == b"hello"
+
+    run(main())
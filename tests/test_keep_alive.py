@@ -0,0 +1,115 @@
+"""
+Tests for keeping connections open between requests and answering
+responses the application abandoned.
+
+    pytest tests/
+"""
+
+import asyncio
+
+from helpers import app, head_and_body, run
+from litmus import TestClient
+
+
+def test_keep_alive_timeout_is_advertised():
+    async def main():
+        async with TestClient(app, lifespan="off", keep_alive=5) as client:
+            response = await client.request(b"GET /length HTTP/1.1\r\n\r\n")
+
+        status, headers, body = head_and_body(response)
+        assert headers[b"keep-alive"] == b"timeout=5"
+        assert b"connection" not in headers
+
+    run(main())
+
+
+def test_idle_connection_is_closed_after_the_keep_alive_timeout():
+    async def main():
+        async with TestClient(
+            app,
+            lifespan="off",
+            keep_alive=0.1,
+            keep_alive_interval=0.05,
+            stall_threshold=None,
+        ) as client:
+            conn = client.connect()
+            response = await conn.request(b"GET /length HTTP/1.1\r\n\r\n")
+            assert response.endswith(b"hello")
+
+            assert await conn.read_until_closed() == b""
+            assert client.server.metrics()["keep_alive_closes"] == 1
+
+    run(main())
+
+
+def test_long_handler_is_not_cut_off_by_the_keep_alive_timeout():
+    async def slow(scope, send, receive):
+        await asyncio.sleep(0.3)
+        await app(scope, send, receive)
+
+    async def main():
+        async with TestClient(
+            slow,
+            lifespan="off",
+            keep_alive=0.1,
+            keep_alive_interval=0.05,
+            stall_threshold=None,
+        ) as client:
+            conn = client.connect()
+            response = await conn.request(b"GET /length HTTP/1.1\r\n\r\n")
+
+            status, headers, body = head_and_body(response)
+            assert status == b"HTTP/1.1 200 OK"
+            assert body == b"hello"
+            assert not conn.closed
+
+    run(main())
+
+
+def test_crash_before_responding_is_answered_with_500():
+    async def main():
+        async with TestClient(
+            app,
+            lifespan="off",
+            keep_alive_interval=0.05,
+            stall_threshold=None,
+        ) as client:
+            response = await client.request(b"GET /crash HTTP/1.1\r\n\r\n")
+
+        assert response.startswith(b"HTTP/1.1 500 Internal Server Error\r\n")
+
+    run(main())
+
+
+def test_silent_application_is_answered_with_500():
+    async def main():
+        async with TestClient(
+            app,
+            lifespan="off",
+            keep_alive_interval=0.05,
+            stall_threshold=None,
+        ) as client:
+            response = await client.request(b"GET /silent HTTP/1.1\r\n\r\n")
+            metrics = client.server.metrics()
+
+        assert response.startswith(b"HTTP/1.1 500 Internal Server Error\r\n")
+        assert metrics["requests"]["GET"]["5xx"] == 1
+
+    run(main())
+
+
+def test_crash_mid_response_closes_the_connection():
+    async def main():
+        async with TestClient(
+            app,
+            lifespan="off",
+            keep_alive_interval=0.05,
+            stall_threshold=None,
+        ) as client:
+            conn = client.connect()
+            response = await conn.request(b"GET /half HTTP/1.1\r\n\r\n")
+
+            assert head_and_body(response)[2] == b"abc"
+            assert conn.closed
+
+    run(main())
@@ -0,0 +1,71 @@
+"""
+Tests for the application's lifespan startup and shutdown events.
+
+    pytest tests/
+"""
+
+import asyncio
+
+from helpers import run
+from litmus import TestClient
+from litmus.adapters import ASGIAdapter
+
+
+def test_startup_and_shutdown_events():
+    events = []
+
+    async def asgi_app(scope, receive, send):
+        assert scope["type"] == "lifespan"
+        events.append((await receive())["type"])
+        await send({"type": "lifespan.startup.complete"})
+        events.append((await receive())["type"])
+        await send({"type": "lifespan.shutdown.complete"})
+
+    async def main():
+        async with TestClient(ASGIAdapter(asgi_app), lifespan="on"):
+            assert events == ["lifespan.startup"]
+
+        assert events == ["lifespan.startup", "lifespan.shutdown"]
+
+    run(main())
+
+
+def test_failed_startup_is_raised():
+    async def asgi_app(scope, receive, send):
+        await receive()
+        await send({"type": "lifespan.startup.failed", "message": "no database"})
+
+    async def main():
+        try:
+            async with TestClient(ASGIAdapter(asgi_app), lifespan="on"):
+                pass
+        except Exception as e:
+            assert "no database" in str(e)
+        else:
+            raise AssertionError("startup did not fail")
+
+    run(main())
+
+
+def test_duplicate_lifespan_reply_is_rejected():
+    errors = []
+
+    async def asgi_app(scope, receive, send):
+        await receive()
+        await send({"type": "lifespan.startup.complete"})
+        await asyncio.sleep(0.05)
+        try:
+            await send({"type": "lifespan.startup.complete"})
+        except RuntimeError as e:
+            errors.append(e)
+
+        await receive()
+        await send({"type": "lifespan.shutdown.complete"})
+
+    async def main():
+        async with TestClient(ASGIAdapter(asgi_app), lifespan="on"):
+            await asyncio.sleep(0.1)
+
+        assert len(errors) == 1
+
+    run(main())
@@ -0,0 +1,48 @@
+"""
+Tests for closing connections after a number of requests.
+
+    pytest tests/
+"""
+
+from helpers import app, head_and_body, run
+from litmus import TestClient
+
+
+def test_connection_is_closed_after_the_last_request():
+    async def main():
+        async with TestClient(
+            app, lifespan="off", max_requests_per_connection=3
+        ) as client:
+            conn = client.connect()
+            for _ in range(2):
+                response = await conn.request(b"GET /length HTTP/1.1\r\n\r\n")
+                status, headers, body = head_and_body(response)
+                assert b"connection" not in headers
+                assert body == b"hello"
+                assert not conn.closed
+
+            response = await conn.request(b"GET /length HTTP/1.1\r\n\r\n")
+            status, headers, body = head_and_body(response)
+            assert headers[b"connection"] == b"close"
+            assert b"keep-alive" not in headers
+            assert body == b"hello"
+            assert conn.closed
+
+    run(main())
+
+
+def test_last_streamed_response_is_close_delimited():
+    async def main():
+        async with TestClient(
+            app, lifespan="off", max_requests_per_connection=1
+        ) as client:
+            conn = client.connect()
+            response = await conn.request(b"GET /stream HTTP/1.1\r\n\r\n")
+
+            status, headers, body = head_and_body(response)
+            assert headers[b"connection"] == b"close"
+            assert b"transfer-encoding" not in headers
+            assert body == b"hello world"
+            assert conn.closed
+
+    run(main())
@@ -0,0 +1,82 @@
+"""
+Tests for the server's metrics counters.
+
+    pytest tests/
+"""
+
+import asyncio
+
+from helpers import app, read_body, run
+from litmus import TestClient
+
+
+def test_requests_are_counted():
+    async def main():
+        async with TestClient(app, lifespan="off") as client:
+            conn = client.connect()
+            first = await conn.request(b"GET /length HTTP/1.1\r\n\r\n")
+            second = await conn.request(b"POST /missing HTTP/1.1\r\ncontent-length: 0\r\n\r\n")
+            metrics = client.server.metrics()
+
+        assert metrics["connections_accepted"] == 1
+        assert metrics["connections_active"] == 1
+        assert metrics["requests"]["GET"]["2xx"] == 1
+        assert metrics["requests"]["POST"]["4xx"] == 1
+        assert metrics["sent_bytes"] == len(first) + len(second)
+        assert metrics["request_duration_seconds"]["count"] == 2
+
+    run(main())
+
+
+def test_parse_errors_are_counted():
+    async def main():
+        async with TestClient(app, lifespan="off") as client:
+            response = await client.request(b"NOT HTTP\r\n\r\n")
+            metrics = client.server.metrics()
+
+        assert response.startswith(b"HTTP/1.1 400 Bad Request\r\n")
+        assert metrics["parse_errors"] == 1
+
+    run(main())
+
+
+def test_dropped_request_is_counted_as_unanswered():
+    async def slow(scope, send, receive):
+        await asyncio.sleep(0.1)
+
+    async def main():
+        async with TestClient(slow, lifespan="off") as client:
+            conn = client.connect()
+            conn.send(b"GET / HTTP/1.1\r\n\r\n")
+            conn.close()
+            metrics = client.server.metrics()
+
+        assert metrics["requests_unanswered"] == 1
+        assert metrics["requests"]["GET"]["1xx"] == 0
+
+    run(main())
+
+
+def test_malformed_body_chunk_closes_the_connection():
+    errors = []
+
+    async def reading(scope, send, receive):
+        try:
+            await read_body(receive)
+        except ConnectionResetError as e:
+            errors.append(e)
+
+    async def main():
+        async with TestClient(reading, lifespan="off") as client:
+            conn = client.connect()
+            conn.send(b"POST / HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n")
+            response = await conn.request(b"zz\r\nhello\r\n")
+            metrics = client.server.metrics()
+
+            assert response == b""
+            assert conn.closed
+
+        assert metrics["parse_errors"] == 1
+        assert len(errors) == 1
+
+    run(main())
@@ -0,0 +1,122 @@
+"""
+Tests for the health and readiness probes answered by the server.
+
+    pytest tests/
+"""
+
+import asyncio
+import time
+
+from helpers import app, head_and_body, run
+from litmus import TestClient
+
+
+def test_probes_are_answered_without_the_application():
+    called = []
+
+    async def recording(scope, send, receive):
+        called.append(scope["path"])
+        await app(scope, send, receive)
+
+    async def main():
+        async with TestClient(
+            recording,
+            lifespan="off",
+            health_path="/healthz",
+            ready_path="/readyz",
+        ) as client:
+            conn = client.connect()
+
+            response = await conn.request(b"GET /healthz HTTP/1.1\r\n\r\n")
+            status, headers, body = head_and_body(response)
+            assert status == b"HTTP/1.1 200 OK"
+            assert b"connection" not in headers
+            assert body == b"ok"
+
+            # Probes keep the connection open for the next one.
+            response = await conn.request(b"HEAD /readyz HTTP/1.1\r\n\r\n")
+            status, headers, body = head_and_body(response)
+            assert status == b"HTTP/1.1 200 OK"
+            assert headers[b"content-length"] == b"5"
+            assert body == b""
+
+            response = await conn.request(b"GET /length HTTP/1.1\r\n\r\n")
+            assert response.endswith(b"hello")
+
+            response = await conn.request(
+                b"GET /healthz HTTP/1.1\r\nconnection: close\r\n\r\n"
+            )
+            assert head_and_body(response)[1][b"connection"] == b"close"
+            assert conn.closed
+
+        assert called == ["/length"]
+
+    run(main())
+
+
+def test_probe_with_a_body_closes_the_connection():
+    async def main():
+        async with TestClient(app, lifespan="off", health_path="/healthz") as client:
+            conn = client.connect()
+            response = await conn.request(
+                b"GET /healthz HTTP/1.1\r\ncontent-length: 4\r\n\r\nbody"
+            )
+
+            assert head_and_body(response)[1][b"connection"] == b"close"
+            assert conn.closed
+
+    run(main())
+
+
+def test_stalled_event_loop_fails_the_health_probe():
+    async def main():
+        async with TestClient(
+            app,
+            lifespan="off",
+            health_path="/healthz",
+            heartbeat_timeout=0.1,
+            keep_alive_interval=0.05,
+            stall_threshold=None,
+        ) as client:
+            conn = client.connect()
+
+            # Blocks the loop so the server misses its heartbeats, the
+            # probe is answered as soon as it is sent.
+            time.sleep(0.3)
+            conn.send(b"GET /healthz HTTP/1.1\r\n\r\n")
+            status, headers, body = head_and_body(conn.receive())
+            assert status == b"HTTP/1.1 503 Service Unavailable"
+            assert body == b"event loop stalled"
+
+            await asyncio.sleep(0.1)
+            response = await conn.request(b"GET /healthz HTTP/1.1\r\n\r\n")
+            assert head_and_body(response)[0] == b"HTTP/1.1 200 OK"
+
+    run(main())
+
+
+def test_readiness_fails_while_draining():
+    release = asyncio.Event()
+
+    async def slow(scope, send, receive):
+        if scope["path"] == "/slow":
+            await release.wait()
+        await app(scope, send, receive)
+
+    async def main():
+        async with TestClient(slow, lifespan="off", ready_path="/readyz") as client:
+            probe = client.connect()
+            busy = client.connect()
+            busy.send(b"GET /slow HTTP/1.1\r\n\r\n")
+
+            client.server.shutdown()
+            await asyncio.sleep(0)
+
+            response = await probe.request(b"GET /readyz HTTP/1.1\r\n\r\n")
+            status, headers, body = head_and_body(response)
+            assert status == b"HTTP/1.1 503 Service Unavailable"
+            assert body == b"not ready"
+
+            release.set()
+
+    run(main())
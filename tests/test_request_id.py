@@ -0,0 +1,88 @@
+"""
+Tests for assigning each request an ID.
+
+    pytest tests/
+"""
+
+import uuid
+
+from helpers import head_and_body, run
+from litmus import TestClient
+
+REQUEST = b"GET / HTTP/1.1\r\nx-request-id: from-proxy\r\n\r\n"
+
+
+def recording(scopes, headers=()):
+    async def app(scope, send, receive):
+        scopes.append(scope)
+        await send.send_start(200, [(b"content-length", b"0"), *headers])
+        await send.send_body(False, b"")
+
+    return app
+
+
+def test_id_is_generated():
+    scopes = []
+
+    async def main():
+        async with TestClient(recording(scopes), lifespan="off") as client:
+            response = await client.request(REQUEST)
+            await client.request(REQUEST)
+
+        first, second = (scope["request_id"] for scope in scopes)
+        assert uuid.UUID(first).version == 4
+        assert first != second
+
+        # Only echoed when enabled.
+        assert b"x-request-id" not in head_and_body(response)[1]
+
+    run(main())
+
+
+def test_id_from_a_trusted_proxy_is_reused():
+    scopes = []
+
+    async def main():
+        async with TestClient(
+            recording(scopes),
+            lifespan="off",
+            client="10.0.0.2:41000",
+            trusted_proxies=["10.0.0.0/8"],
+        ) as client:
+            await client.request(REQUEST)
+
+        assert scopes[0]["request_id"] == "from-proxy"
+
+    run(main())
+
+
+def test_id_is_echoed_in_the_response():
+    scopes = []
+
+    async def main():
+        async with TestClient(
+            recording(scopes), lifespan="off", echo_request_id=True
+        ) as client:
+            response = await client.request(REQUEST)
+
+        # The untrusted client's ID is replaced with one of the server's.
+        assert scopes[0]["request_id"] != "from-proxy"
+        headers = head_and_body(response)[1]
+        assert headers[b"x-request-id"] == scopes[0]["request_id"].encode()
+
+    run(main())
+
+
+def test_application_id_takes_priority():
+    scopes = []
+    app = recording(scopes, headers=[(b"x-request-id", b"from-app")])
+
+    async def main():
+        async with TestClient(app, lifespan="off", echo_request_id=True) as client:
+            response = await client.request(REQUEST)
+
+        status, headers, body = head_and_body(response)
+        assert headers[b"x-request-id"] == b"from-app"
+        assert response.count(b"x-request-id") == 1
+
+    run(main())
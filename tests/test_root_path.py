@@ -0,0 +1,95 @@
+"""
+Tests for the root path applications are mounted at.
+
+    pytest tests/
+"""
+
+from helpers import run
+from litmus import TestClient
+
+REQUEST = b"GET /items HTTP/1.1\r\nx-forwarded-prefix: /api/\r\n\r\n"
+
+
+def recording(scopes):
+    async def app(scope, send, receive):
+        scopes.append(scope)
+        await send.send_start(200, [(b"content-length", b"0")])
+        await send.send_body(False, b"")
+
+    return app
+
+
+def test_configured_root_path():
+    scopes = []
+
+    async def main():
+        async with TestClient(recording(scopes), lifespan="off", root_path="/app") as client:
+            await client.request(b"GET /items HTTP/1.1\r\n\r\n")
+
+        assert scopes[0]["root_path"] == "/app"
+
+    run(main())
+
+
+def test_forwarded_prefix_from_a_trusted_proxy():
+    scopes = []
+
+    async def main():
+        async with TestClient(
+            recording(scopes),
+            lifespan="off",
+            client="10.0.0.2:41000",
+            root_path="/app",
+            forwarded_prefix=True,
+            trusted_proxies=["10.0.0.0/8"],
+        ) as client:
+            await client.request(REQUEST)
+
+        assert scopes[0]["root_path"] == "/api"
+
+    run(main())
+
+
+def test_forwarded_prefix_from_an_untrusted_peer_is_ignored():
+    scopes = []
+
+    async def main():
+        async with TestClient(
+            recording(scopes),
+            lifespan="off",
+            client="192.168.1.2:41000",
+            root_path="/app",
+            forwarded_prefix=True,
+            trusted_proxies=["10.0.0.0/8"],
+        ) as client:
+            await client.request(REQUEST)
+
+        # Without any trusted proxies no peer can set the prefix.
+        async with TestClient(
+            recording(scopes),
+            lifespan="off",
+            root_path="/app",
+            forwarded_prefix=True,
+        ) as client:
+            await client.request(REQUEST)
+
+        assert [scope["root_path"] for scope in scopes] == ["/app", "/app"]
+
+    run(main())
+
+
+def test_forwarded_prefix_is_ignored_unless_enabled():
+    scopes = []
+
+    async def main():
+        async with TestClient(
+            recording(scopes),
+            lifespan="off",
+            client="10.0.0.2:41000",
+            trusted_proxies=["10.0.0.0/8"],
+        ) as client:
+            await client.request(REQUEST)
+
+        assert scopes[0]["root_path"] == ""
+
+    run(main())
@@ -0,0 +1,69 @@
+"""
+Tests for the scope dict handed to applications.
+
+    pytest tests/
+"""
+
+from helpers import run
+from litmus import TestClient
+
+
+def recording(scopes):
+    async def app(scope, send, receive):
+        scopes.append(scope)
+        await send.send_start(200, [(b"content-length", b"0")])
+        await send.send_body(False, b"")
+
+    return app
+
+
+def test_scope_has_asgi_compatible_keys():
+    scopes = []
+
+    async def main():
+        async with TestClient(
+            recording(scopes),
+            lifespan="off",
+            client="10.0.0.2:41000",
+            server="10.0.0.1:8080",
+        ) as client:
+            await client.request(
+                b"GET /caf%C3%A9/a%2Fb?q=1&r=%20 HTTP/1.1\r\n"
+                b"Host: test\r\nX-Custom: One\r\nX-Custom: Two\r\n\r\n"
+            )
+
+        scope = scopes[0]
+        assert scope["type"] == "http"
+        assert scope["http_version"] == "1.1"
+        assert scope["method"] == "GET"
+        assert scope["scheme"] == "http"
+        assert scope["path"] == "/café/a/b"
+        assert scope["raw_path"] == b"/caf%C3%A9/a%2Fb"
+        assert scope["query_string"] == b"q=1&r=%20"
+        assert scope["root_path"] == ""
+        assert scope["client"] == ("10.0.0.2", 41000)
+        assert scope["server"] == ("10.0.0.1", 8080)
+
+        # Names are lower cased bytes with the order and repeats kept.
+        assert scope["headers"] == [
+            (b"host", b"test"),
+            (b"x-custom", b"One"),
+            (b"x-custom", b"Two"),
+        ]
+
+    run(main())
+
+
+def test_scope_over_tls_and_http_10():
+    scopes = []
+
+    async def main():
+        async with TestClient(recording(scopes), lifespan="off", tls=True) as client:
+            await client.request(b"GET / HTTP/1.0\r\n\r\n")
+
+        scope = scopes[0]
+        assert scope["scheme"] == "https"
+        assert scope["http_version"] == "1.0"
+        assert scope["query_string"] == b""
+
+    run(main())
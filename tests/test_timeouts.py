@@ -0,0 +1,71 @@
+"""
+Tests for the header, body and write timeouts applied while a request is
+being read or its response written.
+
+    pytest tests/
+"""
+
+import asyncio
+
+from helpers import app, head_and_body, read_body, run
+from litmus import TestClient
+
+TICK = {"keep_alive_interval": 0.05, "stall_threshold": None}
+
+
+def test_partial_head_is_answered_with_408():
+    async def main():
+        async with TestClient(app, lifespan="off", header_timeout=0.1, **TICK) as client:
+            conn = client.connect()
+            conn.send(b"GET /length HTTP/1.1\r\nhost: te")
+            response = await conn.read_until_closed()
+
+        status, headers, body = head_and_body(response)
+        assert status == b"HTTP/1.1 408 Request Timeout"
+        assert headers[b"connection"] == b"close"
+
+    run(main())
+
+
+def test_trickled_body_is_answered_with_408():
+    errors = []
+
+    async def reading(scope, send, receive):
+        try:
+            await read_body(receive)
+        except ConnectionResetError as e:
+            errors.append(e)
+
+    async def main():
+        async with TestClient(reading, lifespan="off", body_timeout=0.1, **TICK) as client:
+            conn = client.connect()
+            conn.send(b"POST / HTTP/1.1\r\ncontent-length: 10\r\n\r\nabc")
+            response = await conn.read_until_closed()
+
+        status, headers, body = head_and_body(response)
+        assert status == b"HTTP/1.1 408 Request Timeout"
+        assert headers[b"connection"] == b"close"
+
+        # The application stops waiting for the rest of the body.
+        assert len(errors) == 1
+
+    run(main())
+
+
+def test_client_not_reading_the_response_is_closed():
+    payload = b"x" * (256 * 1024)
+
+    async def large(scope, send, receive):
+        await send.send_start(200, [(b"content-length", str(len(payload)).encode())])
+        await send.send_body(False, payload)
+
+    async def main():
+        async with TestClient(large, lifespan="off", write_timeout=0.1, **TICK) as client:
+            conn = client.connect()
+            conn.send(b"GET / HTTP/1.1\r\n\r\n")
+
+            # The client never receives so the server cannot write the rest.
+            await asyncio.sleep(0.3)
+            assert conn.closed
+
+    run(main())
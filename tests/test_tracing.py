@@ -0,0 +1,67 @@
+"""
+Tests for exporting the server's spans over OTLP, these only run when
+litmus is built with the `otlp` feature.
+
+    pytest tests/
+"""
+
+import asyncio
+import threading
+from http.server import BaseHTTPRequestHandler, HTTPServer
+
+from helpers import app, run
+from litmus import TestClient, init_tracing, shutdown_tracing
+
+
+class Collector(BaseHTTPRequestHandler):
+    exports = []
+
+    def do_POST(self):
+        length = int(self.headers["content-length"])
+        self.exports.append((self.path, self.rfile.read(length)))
+
+        self.send_response(200)
+        self.send_header("content-type", "application/x-protobuf")
+        self.send_header("content-length", "0")
+        self.end_headers()
+
+    def log_message(self, *args):
+        pass
+
+
+def test_request_spans_are_exported():
+    collector = HTTPServer(("127.0.0.1", 0), Collector)
+    threading.Thread(target=collector.serve_forever, daemon=True).start()
+    endpoint = "http://127.0.0.1:%d/v1/traces" % collector.server_port
+
+    try:
+        init_tracing(endpoint, service_name="litmus-tests")
+    except RuntimeError as e:
+        import pytest
+
+        pytest.skip(str(e))
+
+    async def main():
+        async with TestClient(
+            app,
+            lifespan="off",
+            keep_alive_interval=0.05,
+            stall_threshold=None,
+        ) as client:
+            await client.request(b"GET /length HTTP/1.1\r\n\r\n")
+
+            # The connection's span ends once its slot has been freed.
+            await asyncio.sleep(0.1)
+
+    try:
+        run(main())
+        shutdown_tracing()
+    finally:
+        collector.shutdown()
+
+    # The export is protobuf encoded, its strings are written as they are.
+    paths = {path for path, _ in Collector.exports}
+    body = b"".join(body for _, body in Collector.exports)
+    assert paths == {"/v1/traces"}
+    for expected in [b"litmus-tests", b"connection", b"request", b"/length", b"GET"]:
+        assert expected in body, expected